@@ -0,0 +1,140 @@
+/// Ping times are stored in the textures on a log scale, in units of this many seconds
+pub const TEXEL_LATENCY_UNIT: f32 = 0.001;
+/// The longest ping time that can be told apart from longer ones in the textures, in seconds
+pub const MAX_TEXEL_LATENCY: f32 = 10.;
+
+/// Encode a ping time in seconds for `fs_main`. 0 is reserved for addresses without a result and
+/// 255 for timeouts, so responses get 1 to 254 on a log scale, which keeps both short and long
+/// ping times distinguishable whatever the color range is set to.
+pub fn latency_to_texel(rtt: f32) -> u8 {
+    let max = (1. + MAX_TEXEL_LATENCY / TEXEL_LATENCY_UNIT).ln();
+    let t = (1. + rtt / TEXEL_LATENCY_UNIT).ln() / max;
+    1 + (t * 253.).round().clamp(0., 253.) as u8
+}
+
+/// The inverse of `latency_to_texel`, the same as `texel_latency` in the shader
+pub fn texel_to_latency(texel: u8) -> f32 {
+    let max = (1. + MAX_TEXEL_LATENCY / TEXEL_LATENCY_UNIT).ln();
+    TEXEL_LATENCY_UNIT * (((texel - 1) as f32 / 253. * max).exp() - 1.)
+}
+
+/// The sRGB color that `fs_main` shows for a ping time in seconds, where a negative time is a
+/// timeout like in the ping files. Ping times of `max` and up get the last color in the palette, as
+/// does every response if `max` is 0. The brightness and gamma settings aren't applied.
+pub fn latency_to_color(rtt: f32, max: f32, palette: Palette) -> [u8; 4] {
+    // the shader draws timeouts as an almost transparent black
+    if rtt < 0. {
+        let [r, g, b] = linear_to_srgb([0.01; 3]);
+        return [r, g, b, (0.01f32 * 255.).round() as u8];
+    }
+    // 0 / 0 would be NaN, which clamping leaves as NaN
+    let t = if max > 0. {
+        (rtt / max).clamp(0., 1.)
+    } else {
+        1.
+    };
+    let [r, g, b] = match palette {
+        Palette::RedCyan => linear_to_srgb([t, 1. - t, 1. - t]),
+        Palette::Viridis => polynomial(&VIRIDIS, t),
+        Palette::Magma => polynomial(&MAGMA, t),
+        Palette::Grayscale => linear_to_srgb([t; 3]),
+    };
+    [r, g, b, 255]
+}
+
+/// The color ramp used to show ping times. The discriminants are what `fs_main` switches on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Palette {
+    #[default]
+    RedCyan = 0,
+    Viridis = 1,
+    Magma = 2,
+    Grayscale = 3,
+}
+impl Palette {
+    pub const ALL: [Self; 4] = [Self::RedCyan, Self::Viridis, Self::Magma, Self::Grayscale];
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::RedCyan => "Red/Cyan",
+            Self::Viridis => "Viridis",
+            Self::Magma => "Magma",
+            Self::Grayscale => "Grayscale",
+        }
+    }
+}
+
+/// The same approximation of the sRGB curve as `srgb_to_linear` in the shader
+fn linear_to_srgb(color: [f32; 3]) -> [u8; 3] {
+    color.map(|c| (c.clamp(0., 1.).powf(1. / 2.2) * 255.).round() as u8)
+}
+
+/// Evaluate one of the polynomial fits of the matplotlib colormaps, which output sRGB colors
+fn polynomial(coefficients: &[[f32; 3]; 7], t: f32) -> [u8; 3] {
+    let mut color = [0.; 3];
+    for c in coefficients.iter().rev() {
+        for (color, c) in color.iter_mut().zip(c) {
+            *color = c + t * *color;
+        }
+    }
+    color.map(|c| (c.clamp(0., 1.) * 255.).round() as u8)
+}
+
+// the same coefficients as `viridis` and `magma` in the shader
+const VIRIDIS: [[f32; 3]; 7] = [
+    [0.277_727_33, 0.005_407_344_5, 0.334_099_8],
+    [0.105_093_04, 1.404_613_5, 1.384_590_1],
+    [-0.330_861_83, 0.214_847_56, 0.095_095_16],
+    [-4.634_230_6, -5.799_101, -19.332_441],
+    [6.228_27, 14.179_933, 56.690_55],
+    [4.776_385, -13.745_145, -65.353_035],
+    [-5.435_456, 4.645_852_6, 26.312_435],
+];
+const MAGMA: [[f32; 3]; 7] = [
+    [-0.002_136_485, -0.000_749_655_05, -0.005_386_128],
+    [0.251_660_54, 0.677_523_24, 2.494_026_6],
+    [8.353_717, -3.577_719_5, 0.314_467_9],
+    [-27.668_733, 14.264_731, -13.649_213],
+    [52.176_14, -27.943_607, 12.944_169],
+    [-50.768_524, 29.046_583, 4.234_153],
+    [18.655_705, -11.489_774, -5.601_961_5],
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_to_color_boundaries() {
+        for palette in Palette::ALL {
+            let first = latency_to_color(0., 0.5, palette);
+            let last = latency_to_color(0.5, 0.5, palette);
+            assert_ne!(first, last, "{palette:?}");
+            assert_eq!(first[3], 255);
+            assert_eq!(last[3], 255);
+            // the ends of each palette are the polynomials or ramps at 0 and 1
+            let (start, end) = match palette {
+                Palette::RedCyan => (linear_to_srgb([0., 1., 1.]), linear_to_srgb([1., 0., 0.])),
+                Palette::Viridis => (polynomial(&VIRIDIS, 0.), polynomial(&VIRIDIS, 1.)),
+                Palette::Magma => (polynomial(&MAGMA, 0.), polynomial(&MAGMA, 1.)),
+                Palette::Grayscale => (linear_to_srgb([0.; 3]), linear_to_srgb([1.; 3])),
+            };
+            assert_eq!(first[..3], start, "{palette:?}");
+            assert_eq!(last[..3], end, "{palette:?}");
+            // past the max is clamped to it
+            assert_eq!(latency_to_color(2., 0.5, palette), last, "{palette:?}");
+            assert_eq!(
+                latency_to_color(f32::MAX, 0.5, palette),
+                last,
+                "{palette:?}"
+            );
+            // timeouts are the same whatever the palette or max
+            let timeout = latency_to_color(-1., 0.5, palette);
+            let [r, g, b] = linear_to_srgb([0.01; 3]);
+            assert_eq!(timeout, [r, g, b, 3]);
+            assert_eq!(latency_to_color(-1., 0., palette), timeout);
+            // with no range at all, every response is past the max
+            assert_eq!(latency_to_color(0., 0., palette), last, "{palette:?}");
+            assert_eq!(latency_to_color(0.1, 0., palette), last, "{palette:?}");
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::path::{Path, PathBuf};
+
+/// The subcommand that the config file sets the defaults of
+const SUBCOMMAND: &str = "ping";
+
+/// The directory that pinger keeps its settings in between runs
+pub fn dir() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_dir.join("pinger"))
+}
+
+/// The settings in `config.toml`, which only has a `[ping]` table so far
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    ping: toml::Table,
+}
+
+/// Make the values in `config.toml` the defaults of the flags of `pinger ping`, so they're used
+/// unless the flag is given. The keys are the long names of the flags, like `concurrency = 5000`
+/// or `subnets = ["10.0.0.0/8"]`, and `true` turns a switch on. A missing file changes nothing.
+pub fn with_defaults(command: clap::Command) -> Result<clap::Command, String> {
+    let Some(path) = dir().map(|dir| dir.join("config.toml")) else {
+        return Ok(command);
+    };
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(command),
+        Err(e) => return Err(format!("{}: {e}", path.display())),
+    };
+    let config =
+        toml::from_str::<ConfigFile>(&contents).map_err(|e| format!("{}: {e}", path.display()))?;
+
+    // Check every setting before changing anything, since the command can't be changed fallibly
+    let subcommand = command.find_subcommand(SUBCOMMAND).unwrap();
+    let mut defaults = vec![];
+    for (key, value) in config.ping {
+        let id = key.replace('-', "_");
+        if !subcommand
+            .get_arguments()
+            .any(|arg| arg.get_id() == id.as_str())
+        {
+            return Err(format!("{}: unknown setting {key:?}", path.display()));
+        }
+        let values = match value {
+            toml::Value::Array(values) => values.into_iter().map(to_arg).collect(),
+            value => to_arg(value).map(|value| vec![value]),
+        };
+        let values = values.map_err(|e| format!("{}: {key}: {e}", path.display()))?;
+        defaults.push((id, values));
+    }
+    Ok(command.mut_subcommand(SUBCOMMAND, |mut subcommand| {
+        for (id, values) in defaults {
+            subcommand = subcommand.mut_arg(id, |arg| arg.default_values(values));
+        }
+        subcommand
+    }))
+}
+
+/// The value as it would be written on the command line
+fn to_arg(value: toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(s) => Ok(s),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        value => Err(format!("{} isn't a valid value", value.type_str())),
+    }
+}
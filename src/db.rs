@@ -0,0 +1,72 @@
+use std::{
+    iter,
+    path::Path,
+    sync::mpsc::{self, Receiver, Sender},
+    thread::JoinHandle,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use pinger::PingResult;
+use rusqlite::{params, Connection};
+
+/// The most results to insert in one transaction. Results are committed whenever the writer has
+/// caught up, so this only matters when it's behind.
+const MAX_BATCH_SIZE: usize = 10_000;
+
+/// Inserts results into a SQLite database on a thread of its own, so the scan never waits on it
+pub struct DbWriter {
+    tx: Sender<PingResult>,
+    handle: JoinHandle<rusqlite::Result<()>>,
+}
+impl DbWriter {
+    /// Open or create the database at the given path and record the scan's settings in its
+    /// `metadata` table, replacing those of any earlier scan. Results are added to the `results`
+    /// table after any that are already there.
+    pub fn open(path: &Path, settings: &[(&str, String)]) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS results (ip TEXT NOT NULL, rtt_ms REAL, ts INTEGER NOT NULL);
+             CREATE TABLE IF NOT EXISTS metadata (key TEXT PRIMARY KEY, value TEXT NOT NULL);",
+        )?;
+        for (key, value) in settings {
+            conn.execute(
+                "INSERT OR REPLACE INTO metadata (key, value) VALUES (?1, ?2)",
+                params![key, value],
+            )?;
+        }
+        let (tx, rx) = mpsc::channel();
+        let handle = std::thread::spawn(move || insert_results(conn, rx));
+        Ok(Self { tx, handle })
+    }
+    /// Queue a result to be inserted. If inserting has failed, the error is returned by `finish`.
+    pub fn insert(&self, result: PingResult) {
+        let _ = self.tx.send(result);
+    }
+    /// Wait for the queued results to be committed
+    pub fn finish(self) -> rusqlite::Result<()> {
+        drop(self.tx);
+        self.handle.join().unwrap()
+    }
+}
+
+fn insert_results(mut conn: Connection, rx: Receiver<PingResult>) -> rusqlite::Result<()> {
+    // Wait for a result, then insert it along with everything else that's queued up by then
+    while let Ok(first) = rx.recv() {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let transaction = conn.transaction()?;
+        {
+            let mut insert = transaction
+                .prepare_cached("INSERT INTO results (ip, rtt_ms, ts) VALUES (?1, ?2, ?3)")?;
+            for result in iter::once(first).chain(rx.try_iter().take(MAX_BATCH_SIZE - 1)) {
+                // timeouts are NULL
+                let rtt_ms = result.rtt.map(|rtt| rtt.as_secs_f64() * 1e3);
+                insert.execute(params![result.addr.to_string(), rtt_ms, ts])?;
+            }
+        }
+        transaction.commit()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,872 @@
+use ipnet::{Ipv4AddrRange, Ipv4Net};
+use iprange::IpRange;
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use surge_ping::SurgeError;
+use tokio::{
+    sync::{
+        mpsc::{self, Receiver, Sender, UnboundedSender},
+        Notify, OwnedSemaphorePermit, Semaphore,
+    },
+    task::JoinHandle,
+};
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+
+#[cfg(target_os = "linux")]
+const ENOBUFS: i32 = 105;
+#[cfg(windows)]
+const ENOBUFS: i32 = 10055;
+#[cfg(not(any(target_os = "linux", windows)))]
+const ENOBUFS: i32 = 55;
+/// How many results can be waiting to be collected or consumed before the scan stops sending pings
+/// to let them catch up. This is well above how many pings are normally in flight, so that it only
+/// kicks in when the consumer falls behind.
+const CHANNEL_CAPACITY: usize = 1 << 16;
+/// The adaptive timeout is this many times the given percentile of the recent ping times
+const ADAPTIVE_TIMEOUT_MULTIPLIER: f64 = 3.;
+const ADAPTIVE_TIMEOUT_PERCENTILE: f64 = 0.95;
+/// The adaptive timeout never goes below this, so a few lucky pings can't make everything time out
+const MIN_ADAPTIVE_TIMEOUT: Duration = Duration::from_millis(10);
+/// The configured timeout is used until this many pings have come back
+const MIN_RTT_SAMPLES: u64 = 100;
+/// Once this many ping times have been recorded the counts are halved, so the percentile follows
+/// the recent ping times rather than the whole scan
+const RTT_WINDOW: u64 = 10_000;
+/// Ping times are counted in buckets a quarter of an octave wide, from 1µs to over an hour
+const RTT_BUCKETS_PER_OCTAVE: f64 = 4.;
+const NUM_RTT_BUCKETS: usize = 128;
+/// How to get permission to send pings, for when opening the socket or sending a ping is denied
+pub const PERMISSION_HINT: &str = "\
+Sending pings requires permission to open ICMP sockets. Either:
+  - run pinger as root (e.g. with sudo),
+  - grant the binary the raw socket capability with `sudo setcap cap_net_raw+ep <path to pinger>`, or
+  - allow unprivileged ICMP sockets for your group with `sudo sysctl net.ipv4.ping_group_range=\"0 2147483647\"`";
+
+/// What to scan and how fast. Built with [`PingConfig::builder`], or
+/// [`PingConfig::stream_builder`] for [`PingEngine::scan_stream`].
+#[derive(Debug, Clone)]
+pub struct PingConfig {
+    range: IpRange<Ipv4Net>,
+    /// Whether the addresses come from a stream rather than the range, which is then empty
+    stream: bool,
+    all_addresses: bool,
+    stride: usize,
+    speed_factor: usize,
+    concurrency: Option<usize>,
+    rate: Option<f64>,
+    num_retries: usize,
+    timeout: Duration,
+    max_adaptive_timeout: Option<Duration>,
+    source: Option<Ipv4Addr>,
+    interface: Option<String>,
+    identifier_base: u16,
+    tos: Option<u8>,
+    capture: Option<UnboundedSender<Packet>>,
+}
+impl PingConfig {
+    /// Start configuring a scan of the given range, with the same defaults as `pinger ping`
+    pub fn builder(range: IpRange<Ipv4Net>) -> PingConfigBuilder {
+        PingConfigBuilder(Self {
+            range,
+            stream: false,
+            all_addresses: false,
+            stride: 1,
+            speed_factor: 2,
+            concurrency: None,
+            rate: None,
+            num_retries: 5,
+            timeout: Duration::from_secs(2),
+            max_adaptive_timeout: None,
+            source: None,
+            interface: None,
+            // like ping(8), so other processes pinging the same hosts are unlikely to share IDs
+            identifier_base: std::process::id() as u16,
+            tos: None,
+            capture: None,
+        })
+    }
+    /// Start configuring a scan of the addresses from a stream, which has no range to scan
+    pub fn stream_builder() -> PingConfigBuilder {
+        let mut builder = Self::builder(IpRange::new());
+        builder.0.stream = true;
+        builder
+    }
+    pub fn range(&self) -> &IpRange<Ipv4Net> {
+        &self.range
+    }
+    pub fn all_addresses(&self) -> bool {
+        self.all_addresses
+    }
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+    /// The ICMP identifier that the pings to the given address are sent with, see
+    /// [`PingConfigBuilder::identifier_base`]
+    fn identifier(&self, addr: Ipv4Addr) -> u16 {
+        self.identifier_base.wrapping_add(u32::from(addr) as u16)
+    }
+    /// The addresses that are pinged, in order
+    fn addresses(&self) -> impl Iterator<Item = Ipv4Addr> + '_ {
+        addresses(&self.range, self.all_addresses).step_by(self.stride)
+    }
+}
+
+pub struct PingConfigBuilder(PingConfig);
+impl PingConfigBuilder {
+    /// Also ping the network and broadcast address of each subnet
+    pub fn all_addresses(mut self, all_addresses: bool) -> Self {
+        self.0.all_addresses = all_addresses;
+        self
+    }
+    /// Only ping every `stride`th address, for a quick sample of a big range
+    pub fn stride(mut self, stride: usize) -> Self {
+        self.0.stride = stride;
+        self
+    }
+    /// A unitless number representing how fast to send pings (higher is faster)
+    pub fn speed_factor(mut self, speed_factor: usize) -> Self {
+        self.0.speed_factor = speed_factor;
+        self
+    }
+    /// The most pings to have in flight at once
+    pub fn concurrency(mut self, concurrency: Option<usize>) -> Self {
+        self.0.concurrency = concurrency;
+        self
+    }
+    /// The most pings to send per second
+    pub fn rate(mut self, rate: Option<f64>) -> Self {
+        self.0.rate = rate;
+        self
+    }
+    /// The number of times to try pinging each address
+    pub fn num_retries(mut self, num_retries: usize) -> Self {
+        self.0.num_retries = num_retries;
+        self
+    }
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.0.timeout = timeout;
+        self
+    }
+    /// Start with the timeout, and then adjust it to a few times the recent ping times, up to the
+    /// given maximum
+    pub fn adaptive_timeout(mut self, max_timeout: Option<Duration>) -> Self {
+        self.0.max_adaptive_timeout = max_timeout;
+        self
+    }
+    /// The local address to send pings from
+    pub fn source(mut self, source: Option<Ipv4Addr>) -> Self {
+        self.0.source = source;
+        self
+    }
+    /// The network interface to send pings from (Linux only)
+    pub fn interface(mut self, interface: Option<String>) -> Self {
+        self.0.interface = interface;
+        self
+    }
+    /// The ICMP identifier that the identifiers of the pings are offset from, which defaults to
+    /// the low bits of the process ID.
+    ///
+    /// Replies are matched to pings by their address, identifier, and sequence number. Each address
+    /// gets `identifier_base` plus its low 16 bits, so neighbouring addresses, which are the ones in
+    /// flight together, have different identifiers, and replies to another process pinging the
+    /// same hosts aren't taken for the scan's. Each retry gets the next sequence number: at 100k
+    /// concurrency replies often arrive after their ping has timed out, and one that turned up
+    /// during the next attempt would otherwise be counted as its reply, making the host look faster
+    /// than it is. On Linux, unprivileged ICMP sockets replace the identifier with the socket's
+    /// port, so the base only matters with raw sockets.
+    pub fn identifier_base(mut self, identifier_base: u16) -> Self {
+        self.0.identifier_base = identifier_base;
+        self
+    }
+    /// The type of service byte to put in the IP header of every ping, which holds the DSCP in its
+    /// upper 6 bits and the ECN in its lower 2 (Unix only)
+    pub fn tos(mut self, tos: Option<u8>) -> Self {
+        self.0.tos = tos;
+        self
+    }
+    /// Send a [`Packet`] for every echo request that goes out and every reply that comes back, for
+    /// recording what the scan put on the wire
+    pub fn capture(mut self, capture: Option<UnboundedSender<Packet>>) -> Self {
+        self.0.capture = capture;
+        self
+    }
+    pub fn build(self) -> Result<PingConfig, ConfigError> {
+        let config = self.0;
+        if config.stride == 0 {
+            return Err(ConfigError::ZeroStride);
+        }
+        if config.speed_factor == 0 {
+            return Err(ConfigError::ZeroSpeedFactor);
+        }
+        if config.concurrency == Some(0) {
+            return Err(ConfigError::ZeroConcurrency);
+        }
+        if let Some(rate) = config.rate {
+            if !(rate > 0. && rate.is_finite()) {
+                return Err(ConfigError::InvalidRate(rate));
+            }
+        }
+        if config.num_retries == 0 {
+            return Err(ConfigError::ZeroRetries);
+        }
+        if !config.stream && config.addresses().next().is_none() {
+            return Err(ConfigError::EmptyRange);
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    ZeroStride,
+    ZeroSpeedFactor,
+    ZeroConcurrency,
+    InvalidRate(f64),
+    ZeroRetries,
+    EmptyRange,
+}
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ZeroStride => write!(f, "the stride must be at least 1"),
+            Self::ZeroSpeedFactor => write!(f, "the speed factor must be at least 1"),
+            Self::ZeroConcurrency => write!(f, "the concurrency must be at least 1"),
+            Self::InvalidRate(rate) => write!(f, "{rate} isn't a valid number of pings per second"),
+            Self::ZeroRetries => write!(f, "each address must be pinged at least once"),
+            Self::EmptyRange => write!(f, "the range has no addresses to ping"),
+        }
+    }
+}
+impl std::error::Error for ConfigError {}
+
+/// Pings every address in a range, with as many pings in flight at once as the config allows
+pub struct PingEngine {
+    config: Arc<PingConfig>,
+    client: Arc<surge_ping::Client>,
+    state: Arc<State>,
+}
+impl PingEngine {
+    /// Open the ICMP socket that the pings are sent from, which fails if we don't have permission
+    /// to send pings
+    pub fn new(config: PingConfig) -> io::Result<Self> {
+        let mut client_config = surge_ping::Config::builder();
+        if let Some(source) = config.source {
+            // Binding a throwaway socket is the simplest way to check that the address is local
+            if let Err(e) = std::net::UdpSocket::bind((source, 0)) {
+                let message = format!("can't send pings from {source}: {e}");
+                return Err(io::Error::new(e.kind(), message));
+            }
+            client_config = client_config.bind(SocketAddr::new(source.into(), 0));
+        }
+        if let Some(ref interface) = config.interface {
+            client_config = client_config.interface(interface);
+        }
+        let client = Arc::new(surge_ping::Client::new(&client_config.build())?);
+        if let Some(tos) = config.tos {
+            if let Err(e) = set_tos(&client, tos) {
+                let message = format!("can't set the type of service to {tos}: {e}");
+                return Err(io::Error::new(e.kind(), message));
+            }
+        }
+        let total = config.addresses().count() as u64;
+        let state = State::new(total, config.concurrency);
+        Ok(Self {
+            config: Arc::new(config),
+            client,
+            state: Arc::new(state),
+        })
+    }
+    pub fn config(&self) -> &PingConfig {
+        &self.config
+    }
+    /// The progress of the current scan
+    pub fn state(&self) -> &Arc<State> {
+        &self.state
+    }
+    /// Ping every address in the range after the first `skip`. The results come out in the same
+    /// order as the addresses. Dropping the stream stops the scan.
+    pub fn scan(&self, skip: u64) -> impl Stream<Item = PingResult> + Send + Unpin {
+        self.state.reset(skip, false);
+        let (handle_tx, handle_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let (result_tx, result_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let config = self.config.clone();
+        let client = self.client.clone();
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            let addrs = tokio_stream::iter(config.addresses().skip(skip as usize));
+            dispatcher(&config, client, state, addrs, handle_tx).await
+        });
+        tokio::spawn(collector(handle_rx, result_tx));
+        ReceiverStream::new(result_rx)
+    }
+    /// Ping the addresses from the given stream as they come in, instead of those in the range,
+    /// with the rest of the config the same. The results come out in the same order as the
+    /// addresses, and the scan ends with the stream.
+    pub fn scan_stream(
+        &self,
+        addrs: impl Stream<Item = Ipv4Addr> + Send + Unpin + 'static,
+    ) -> impl Stream<Item = PingResult> + Send + Unpin {
+        self.state.reset(0, true);
+        let (handle_tx, handle_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let (result_tx, result_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let config = self.config.clone();
+        let client = self.client.clone();
+        let state = self.state.clone();
+        tokio::spawn(async move { dispatcher(&config, client, state, addrs, handle_tx).await });
+        tokio::spawn(collector(handle_rx, result_tx));
+        ReceiverStream::new(result_rx)
+    }
+}
+
+/// The outcome of pinging one address
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PingResult {
+    pub addr: Ipv4Addr,
+    /// The round trip time, or `None` if the address didn't respond
+    pub rtt: Option<Duration>,
+}
+
+/// An ICMP echo request or reply, as seen by the scan. These are rebuilt from what the pings sent and
+/// got back rather than read off the socket, so replies that weren't matched to a ping are missing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    pub time: SystemTime,
+    pub kind: PacketKind,
+    /// The address that was pinged, which is the destination of a request and the source of a reply
+    pub addr: Ipv4Addr,
+    pub identifier: u16,
+    pub sequence: u16,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketKind {
+    Request,
+    Reply,
+}
+
+/// Iterate over the addresses in the range in the order they are pinged
+pub fn addresses(
+    range: &IpRange<Ipv4Net>,
+    all_addresses: bool,
+) -> impl Iterator<Item = Ipv4Addr> + '_ {
+    // the nets are collected first, since iterating over the range itself can't be held across an
+    // await in a spawned task
+    let nets = range.iter().collect::<Vec<_>>();
+    nets.into_iter().flat_map(move |net| {
+        if all_addresses {
+            Ipv4AddrRange::new(net.network(), net.broadcast())
+        } else {
+            net.hosts()
+        }
+    })
+}
+
+/// Iterate over the addresses in the range along with their offsets, which is where their results
+/// are in a ping file of the range. In `10.0.0.0/24`, `10.0.0.1` is at offset 0, or at offset 1
+/// with `all_addresses`.
+pub fn addresses_with_offsets(
+    range: &IpRange<Ipv4Net>,
+    all_addresses: bool,
+) -> impl Iterator<Item = (u64, Ipv4Addr)> + '_ {
+    (0..).zip(addresses(range, all_addresses))
+}
+
+async fn dispatcher(
+    config: &PingConfig,
+    client: Arc<surge_ping::Client>,
+    state: Arc<State>,
+    mut addrs: impl Stream<Item = Ipv4Addr> + Unpin,
+    tx: Sender<(Ipv4Addr, JoinHandle<Option<Duration>>)>,
+) {
+    // The limits on how many pings can be in flight and how often a ping can be sent are only
+    // enforced if they were set. The concurrency comes from the state, since it can be changed
+    // while the scan runs.
+    let mut limit = state.concurrency().unwrap_or(0);
+    let semaphore = (limit > 0).then(|| Arc::new(Semaphore::new(limit)));
+    let mut rate_limiter = config
+        .rate
+        .map(|rate| tokio::time::interval(Duration::from_secs_f64(rate.recip())));
+
+    // For every remaining address, as it comes in:
+    while let Some(addr) = addrs.next().await {
+        // Hold off while the scan is paused. The rate limit starts over afterwards, so the pings
+        // that would have been sent during the pause don't all go out at once.
+        if state.wait_while_paused().await {
+            if let Some(ref mut rate_limiter) = rate_limiter {
+                rate_limiter.reset();
+            }
+        }
+        if state.is_stopped() {
+            break;
+        }
+        // Wait for our turn to send a ping
+        if let Some(ref mut rate_limiter) = rate_limiter {
+            rate_limiter.tick().await;
+        }
+        let permit = match semaphore {
+            Some(ref semaphore) => {
+                let new_limit = state.concurrency().unwrap();
+                adjust_permits(semaphore, limit, new_limit);
+                limit = new_limit;
+                Some(semaphore.clone().acquire_owned().await.unwrap())
+            }
+            None => None,
+        };
+        // Construct a pinger
+        let identifier = config.identifier(addr);
+        let mut pinger = client.pinger(addr.into(), identifier.into()).await;
+        // Set the timout based on the config, or on the recent ping times in adaptive mode
+        let timeout = match config.max_adaptive_timeout {
+            Some(max_timeout) => state.adaptive_timeout(config.timeout, max_timeout),
+            None => config.timeout,
+        };
+        pinger.timeout(timeout);
+        // Add 1 to the running count
+        state.num_running.fetch_add(1, Ordering::Release);
+        // Spawn the worker
+        let capture = config.capture.clone().map(|tx| Capture {
+            tx,
+            addr,
+            identifier,
+        });
+        let handle = tokio::spawn(ping_worker(
+            addr,
+            pinger,
+            state.clone(),
+            config.num_retries,
+            timeout,
+            capture,
+            permit,
+        ));
+        // Send the worker handle to the collector, unless nobody wants the results anymore. This
+        // waits if the results aren't being consumed fast enough.
+        if tx.send((addr, handle)).await.is_err() {
+            break;
+        }
+        // Wait until the running count drops below the max threshold given by the speed factor
+        let num_running = state.num_running.load(Ordering::Acquire);
+        for _ in 0..num_running / config.speed_factor {
+            tokio::task::yield_now().await;
+        }
+    }
+}
+
+/// Add or take away permits to go from one concurrency limit to another. Permits that are in use
+/// can't be taken back, so a lower limit takes effect as the pings in flight finish.
+fn adjust_permits(semaphore: &Arc<Semaphore>, old: usize, new: usize) {
+    if new > old {
+        semaphore.add_permits(new - old);
+    } else if new < old {
+        let semaphore = semaphore.clone();
+        let num_removed = (old - new) as u32;
+        tokio::spawn(async move {
+            if let Ok(permits) = semaphore.acquire_many_owned(num_removed).await {
+                permits.forget();
+            }
+        });
+    }
+}
+
+/// Wait for each ping in the order they were sent, so the results come out in address order
+async fn collector(
+    mut rx: Receiver<(Ipv4Addr, JoinHandle<Option<Duration>>)>,
+    tx: Sender<PingResult>,
+) {
+    while let Some((addr, handle)) = rx.recv().await {
+        let rtt = handle.await.unwrap();
+        if tx.send(PingResult { addr, rtt }).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[tracing::instrument(level = "trace", skip_all, fields(%addr))]
+async fn ping_worker(
+    addr: Ipv4Addr,
+    mut pinger: surge_ping::Pinger,
+    state: Arc<State>,
+    num_retries: usize,
+    timeout: Duration,
+    capture: Option<Capture>,
+    _permit: Option<OwnedSemaphorePermit>,
+) -> Option<Duration> {
+    // Start the ping and await its return. Errors from full socket buffers are retried after a
+    // backoff without using up a retry, for at most the ping timeout in total.
+    let mut i = 1;
+    let mut backoff = Duration::from_millis(1);
+    let backoff_deadline = Instant::now() + timeout;
+    let reply = loop {
+        // each attempt has its own sequence number, so late replies to the earlier ones are ignored
+        let sequence = (i - 1) as u16;
+        let sent = capture.as_ref().map(|_| SystemTime::now());
+        let reply = pinger.ping(sequence.into(), &[]).await;
+        if let (Some(capture), Some(sent)) = (&capture, sent) {
+            capture.record(reply.as_ref().map(|(_, rtt)| *rtt), sent, sequence);
+        }
+        if let Err(ref e) = reply {
+            if is_transient(e) && Instant::now() + backoff < backoff_deadline {
+                tracing::debug!(%addr, "Transient socket error, backing off: {e}");
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                continue;
+            }
+            tracing::debug!(%addr, attempt = i, "Ping failed: {e}");
+        }
+        if i >= num_retries || reply.is_ok() {
+            break reply;
+        }
+        i += 1;
+    };
+    // Now that the ping has returned, add 1 to num_done and subtract 1 from the running count
+    state.num_done.fetch_add(1, Ordering::Release);
+    state.num_running.fetch_sub(1, Ordering::Release);
+    // Keep track of timeouts and errors separately, since an error means the address was never
+    // actually pinged.
+    match reply {
+        Ok((_, rtt)) => state.rtts.record(rtt),
+        Err(SurgeError::Timeout { .. }) => {
+            state.num_timeouts.fetch_add(1, Ordering::Release);
+        }
+        Err(ref e) => {
+            state.num_errors.fetch_add(1, Ordering::Release);
+            tracing::warn!(%addr, "Ping failed: {e}");
+            let permission_denied = matches!(
+                e,
+                SurgeError::IOError(e) if e.kind() == std::io::ErrorKind::PermissionDenied
+            );
+            if permission_denied && !state.permission_hint_shown.swap(true, Ordering::AcqRel) {
+                tracing::error!("{PERMISSION_HINT}");
+            }
+        }
+    }
+    // Return an optional duration based on if the ping timed out or returned successfully.
+    reply.ok().map(|(_, dur)| dur)
+}
+
+/// Where a worker sends the packets of its pings, when they're being captured
+struct Capture {
+    tx: UnboundedSender<Packet>,
+    addr: Ipv4Addr,
+    identifier: u16,
+}
+impl Capture {
+    /// Send the packets of an attempt that was sent at the given time. A request that couldn't be
+    /// sent didn't go out, so it isn't captured.
+    fn record(&self, rtt: Result<Duration, &SurgeError>, sent: SystemTime, sequence: u16) {
+        let packet = |kind, time| Packet {
+            time,
+            kind,
+            addr: self.addr,
+            identifier: self.identifier,
+            sequence,
+        };
+        match rtt {
+            Ok(rtt) => {
+                let _ = self.tx.send(packet(PacketKind::Request, sent));
+                let _ = self.tx.send(packet(PacketKind::Reply, sent + rtt));
+            }
+            Err(SurgeError::Timeout { .. }) => {
+                let _ = self.tx.send(packet(PacketKind::Request, sent));
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// Set the type of service byte on the client's socket, which every ping is sent from
+#[cfg(unix)]
+fn set_tos(client: &surge_ping::Client, tos: u8) -> io::Result<()> {
+    use std::os::fd::BorrowedFd;
+    let socket = client.get_socket();
+    // the client keeps the socket open for longer than this borrow
+    let fd = unsafe { BorrowedFd::borrow_raw(socket.get_native_sock()) };
+    socket2::SockRef::from(&fd).set_tos(tos.into())
+}
+#[cfg(not(unix))]
+fn set_tos(_: &surge_ping::Client, _: u8) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "only supported on Unix",
+    ))
+}
+
+/// Whether a ping error was caused by the socket buffers being full, which goes away on its own
+fn is_transient(e: &SurgeError) -> bool {
+    match e {
+        SurgeError::IOError(e) => {
+            e.kind() == std::io::ErrorKind::WouldBlock || e.raw_os_error() == Some(ENOBUFS)
+        }
+        _ => false,
+    }
+}
+
+/// Counters for the progress of a scan, which are updated as the pings return, and whether the
+/// scan is paused or stopped
+pub struct State {
+    num_done: AtomicU64,
+    num_running: AtomicUsize,
+    num_timeouts: AtomicU64,
+    num_errors: AtomicU64,
+    permission_hint_shown: AtomicBool,
+    paused: AtomicBool,
+    stopped: AtomicBool,
+    resumed: Notify,
+    rtts: RttHistogram,
+    total: u64,
+    /// Whether the addresses are coming from a stream, so there's no telling how many there are
+    streaming: AtomicBool,
+    /// The most pings to have in flight at once, or 0 for no limit
+    concurrency: AtomicUsize,
+}
+impl State {
+    /// The number of addresses that have been pinged, including any that were skipped
+    pub fn num_done(&self) -> u64 {
+        self.num_done.load(Ordering::Acquire)
+    }
+    /// The number of pings in flight
+    pub fn num_running(&self) -> usize {
+        self.num_running.load(Ordering::Acquire)
+    }
+    pub fn num_timeouts(&self) -> u64 {
+        self.num_timeouts.load(Ordering::Acquire)
+    }
+    /// The number of addresses that couldn't be pinged at all
+    pub fn num_errors(&self) -> u64 {
+        self.num_errors.load(Ordering::Acquire)
+    }
+    /// The number of addresses in the range, or `None` if they're coming from a stream
+    pub fn total(&self) -> Option<u64> {
+        (!self.streaming.load(Ordering::Acquire)).then_some(self.total)
+    }
+    /// The most pings to have in flight at once, or `None` if there's no limit
+    pub fn concurrency(&self) -> Option<usize> {
+        match self.concurrency.load(Ordering::Acquire) {
+            0 => None,
+            n => Some(n),
+        }
+    }
+    /// Change the most pings to have in flight at once, which takes effect from the next ping. A
+    /// scan without a limit doesn't get one.
+    pub fn set_concurrency(&self, concurrency: usize) {
+        if self.concurrency().is_some() {
+            self.concurrency
+                .store(concurrency.max(1), Ordering::Release);
+        }
+    }
+    /// Stop sending new pings until the scan is resumed. Pings that are already in flight still
+    /// finish, so their results are written.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Release);
+    }
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Release);
+        self.resumed.notify_waiters();
+    }
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Acquire)
+    }
+    /// Stop sending pings for good, including in later passes. The pings in flight still finish,
+    /// and then the stream of results ends.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+        self.resumed.notify_waiters();
+    }
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Acquire)
+    }
+    /// A few times the recent ping times, clamped to the maximum, or the initial timeout if too
+    /// few pings have come back yet
+    fn adaptive_timeout(&self, initial: Duration, max: Duration) -> Duration {
+        match self.rtts.percentile(ADAPTIVE_TIMEOUT_PERCENTILE) {
+            Some(rtt) => rtt
+                .mul_f64(ADAPTIVE_TIMEOUT_MULTIPLIER)
+                .clamp(MIN_ADAPTIVE_TIMEOUT.min(max), max),
+            None => initial,
+        }
+    }
+    /// Wait until the scan isn't paused or has been stopped, returning whether it had to wait
+    async fn wait_while_paused(&self) -> bool {
+        let mut waited = false;
+        loop {
+            // start listening before checking, so a resume in between isn't missed
+            let resumed = self.resumed.notified();
+            if !self.is_paused() || self.is_stopped() {
+                return waited;
+            }
+            waited = true;
+            resumed.await;
+        }
+    }
+    /// Reset the counters to start another pass over the range or a stream of addresses
+    fn reset(&self, done: u64, streaming: bool) {
+        self.streaming.store(streaming, Ordering::Release);
+        self.num_done.store(done, Ordering::Release);
+        self.num_timeouts.store(0, Ordering::Release);
+        self.num_errors.store(0, Ordering::Release);
+    }
+    fn new(total: u64, concurrency: Option<usize>) -> Self {
+        Self {
+            num_done: AtomicU64::new(0),
+            num_running: AtomicUsize::new(0),
+            num_timeouts: AtomicU64::new(0),
+            num_errors: AtomicU64::new(0),
+            permission_hint_shown: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+            resumed: Notify::new(),
+            rtts: RttHistogram::new(),
+            total,
+            streaming: AtomicBool::new(false),
+            concurrency: AtomicUsize::new(concurrency.unwrap_or(0)),
+        }
+    }
+}
+
+/// Counts of the ping times of recent replies, for estimating their percentiles
+struct RttHistogram {
+    buckets: [AtomicU64; NUM_RTT_BUCKETS],
+    total: AtomicU64,
+}
+impl RttHistogram {
+    fn new() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            total: AtomicU64::new(0),
+        }
+    }
+    fn record(&self, rtt: Duration) {
+        let micros = (rtt.as_secs_f64() * 1e6).max(1.);
+        let bucket = (micros.log2() * RTT_BUCKETS_PER_OCTAVE) as usize;
+        self.buckets[bucket.min(NUM_RTT_BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+        // Only the ping that fills the window does the halving
+        if self.total.fetch_add(1, Ordering::Relaxed) + 1 == RTT_WINDOW {
+            let mut removed = 0;
+            for bucket in &self.buckets {
+                let count = bucket.load(Ordering::Relaxed);
+                bucket.fetch_sub(count - count / 2, Ordering::Relaxed);
+                removed += count - count / 2;
+            }
+            self.total.fetch_sub(removed, Ordering::Relaxed);
+        }
+    }
+    /// The upper edge of the bucket holding the given percentile, or `None` if too few ping times
+    /// have been recorded
+    fn percentile(&self, percentile: f64) -> Option<Duration> {
+        let total = self.total.load(Ordering::Relaxed);
+        if total < MIN_RTT_SAMPLES {
+            return None;
+        }
+        let target = (total as f64 * percentile).ceil() as u64;
+        let mut count = 0;
+        let bucket = self.buckets.iter().position(|bucket| {
+            count += bucket.load(Ordering::Relaxed);
+            count >= target
+        })?;
+        let micros = ((bucket + 1) as f64 / RTT_BUCKETS_PER_OCTAVE).exp2();
+        Some(Duration::from_secs_f64(micros / 1e6))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builder_rejects_invalid_configs() {
+        let range = range(&["10.0.0.0/24"]);
+        assert!(PingConfig::builder(range.clone()).build().is_ok());
+        let config = PingConfig::builder(range.clone())
+            .concurrency(Some(0))
+            .build();
+        assert!(matches!(config, Err(ConfigError::ZeroConcurrency)));
+        let config = PingConfig::builder(range.clone()).stride(0).build();
+        assert!(matches!(config, Err(ConfigError::ZeroStride)));
+        let config = PingConfig::builder(range.clone()).speed_factor(0).build();
+        assert!(matches!(config, Err(ConfigError::ZeroSpeedFactor)));
+        let config = PingConfig::builder(range.clone()).num_retries(0).build();
+        assert!(matches!(config, Err(ConfigError::ZeroRetries)));
+        for rate in [0., -1., f64::NAN, f64::INFINITY] {
+            let config = PingConfig::builder(range.clone()).rate(Some(rate)).build();
+            assert!(matches!(config, Err(ConfigError::InvalidRate(_))), "{rate}");
+        }
+        // any identifier is valid, and they wrap around past the last one
+        let config = PingConfig::builder(range).identifier_base(u16::MAX).build();
+        assert!(config.is_ok());
+    }
+
+    #[test]
+    fn builder_rejects_empty_ranges() {
+        let config = PingConfig::builder(IpRange::new()).build();
+        assert!(matches!(config, Err(ConfigError::EmptyRange)));
+        // a single address is its own host
+        let config = PingConfig::builder(range(&["10.0.0.0/32"])).build();
+        assert!(config.is_ok());
+        // streams have no range of their own
+        assert!(PingConfig::stream_builder().build().is_ok());
+    }
+
+    /// The range iterates over its nets with the shortest prefixes first, rather than by address,
+    /// so the results of the /29 come before those of the /30
+    fn range(nets: &[&str]) -> IpRange<Ipv4Net> {
+        nets.iter().map(|net| net.parse().unwrap()).collect()
+    }
+
+    fn offsets(range: &IpRange<Ipv4Net>, all_addresses: bool) -> Vec<(u64, String)> {
+        addresses_with_offsets(range, all_addresses)
+            .map(|(offset, addr)| (offset, addr.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn offsets_of_hosts() {
+        let range = range(&["10.0.0.0/30", "192.168.0.8/29"]);
+        let expected = [
+            "192.168.0.9",
+            "192.168.0.10",
+            "192.168.0.11",
+            "192.168.0.12",
+            "192.168.0.13",
+            "192.168.0.14",
+            "10.0.0.1",
+            "10.0.0.2",
+        ];
+        let expected = (0..).zip(expected.map(String::from)).collect::<Vec<_>>();
+        assert_eq!(offsets(&range, false), expected);
+    }
+
+    #[test]
+    fn offsets_of_all_addresses() {
+        let range = range(&["10.0.0.0/30", "192.168.0.8/29"]);
+        let expected = (8..16)
+            .map(|i| format!("192.168.0.{i}"))
+            .chain((0..4).map(|i| format!("10.0.0.{i}")));
+        let expected = (0..).zip(expected).collect::<Vec<_>>();
+        assert_eq!(offsets(&range, true), expected);
+    }
+
+    #[test]
+    fn identifiers_are_offset_by_the_low_bits_of_the_address() {
+        let config = PingConfig::builder(range(&["10.0.0.0/24"]))
+            .identifier_base(1000)
+            .build()
+            .unwrap();
+        assert_eq!(config.identifier(Ipv4Addr::new(10, 0, 0, 0)), 1000);
+        assert_eq!(config.identifier(Ipv4Addr::new(10, 0, 0, 1)), 1001);
+        assert_eq!(config.identifier(Ipv4Addr::new(10, 0, 1, 0)), 1256);
+        // only the low 16 bits count, so the same host in another /16 shares an identifier
+        assert_eq!(config.identifier(Ipv4Addr::new(10, 1, 0, 1)), 1001);
+        // and they wrap around past the last identifier
+        assert_eq!(config.identifier(Ipv4Addr::new(10, 0, 255, 255)), 999);
+        let config = PingConfig::builder(range(&["10.0.0.0/24"]))
+            .identifier_base(u16::MAX)
+            .build()
+            .unwrap();
+        assert_eq!(config.identifier(Ipv4Addr::new(10, 0, 0, 1)), 0);
+    }
+}
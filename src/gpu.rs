@@ -2,6 +2,9 @@ use egui_wgpu::renderer::ScreenDescriptor;
 use wgpu::*;
 use winit::{dpi::PhysicalSize, window::Window};
 
+/// The most bind groups that any of the map's pipelines use
+const REQUIRED_BIND_GROUPS: u32 = 4;
+
 pub struct GpuState {
     pub device: Device,
     pub queue: Queue,
@@ -9,35 +12,61 @@ pub struct GpuState {
     pub surface_config: SurfaceConfiguration,
     pub sample_count: u32,
     pub msaa_texture_view: Option<TextureView>,
+    /// The number of nanoseconds per tick of a timestamp query, or `None` if the passes aren't
+    /// being timed
+    pub timestamp_period: Option<f32>,
 }
 impl GpuState {
-    pub async fn new(window: &Window) -> Self {
-        let instance = Instance::new(InstanceDescriptor {
-            backends: Backends::all(),
-            dx12_shader_compiler: Dx12Compiler::default(),
-        });
-
-        let surface = unsafe { instance.create_surface(window) }.unwrap();
-
-        let request_adapter_options = RequestAdapterOptionsBase {
-            power_preference: PowerPreference::HighPerformance,
-            force_fallback_adapter: false,
-            compatible_surface: Some(&surface),
-        };
-        let adapter = instance
-            .request_adapter(&request_adapter_options)
-            .await
-            .unwrap();
+    pub async fn new(window: &Window, args: &Args) -> Result<Self, Error> {
+        let backends = args.backend.map_or(Backends::all(), Backends::from);
+        let power_preference = args.power.into();
+        if args.software {
+            println!("Using a software adapter as requested, so rendering will be slow");
+        }
+        let mut found =
+            Self::request_adapter(window, backends, power_preference, args.software).await?;
+        if found.is_none() && backends != Backends::all() {
+            println!("No adapter found for the requested backend, falling back to any backend");
+            found = Self::request_adapter(window, Backends::all(), power_preference, args.software)
+                .await?;
+        }
+        if found.is_none() && !args.software {
+            println!("No hardware adapter found, falling back to a software adapter");
+            found = Self::request_adapter(window, Backends::all(), power_preference, true).await?;
+        }
+        let (surface, adapter) = found.ok_or(Error::NoAdapter)?;
+        let adapter_info = adapter.get_info();
+        println!(
+            "Using adapter {:?} ({:?})",
+            adapter_info.name, adapter_info.backend
+        );
 
+        let mut features = Features::empty() | Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES;
+        let gpu_profile =
+            args.gpu_profile && adapter.features().contains(Features::TIMESTAMP_QUERY);
+        if gpu_profile {
+            features |= Features::TIMESTAMP_QUERY;
+        } else if args.gpu_profile {
+            println!("The adapter doesn't support timestamp queries, so the GPU won't be profiled");
+        }
+        // Ask for everything the adapter can do, since the defaults can be more than a constrained
+        // GPU supports. What the map needs is checked here, and the block size is fit to the rest.
+        let limits = adapter.limits();
+        if limits.max_bind_groups < REQUIRED_BIND_GROUPS {
+            return Err(Error::Limits(format!(
+                "{REQUIRED_BIND_GROUPS} bind groups are needed, but the adapter only supports {}",
+                limits.max_bind_groups
+            )));
+        }
         let device_descriptor = DeviceDescriptor {
             label: None,
-            features: Features::empty() | Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
-            limits: Limits::default(),
+            features,
+            limits,
         };
         let (device, queue) = adapter
             .request_device(&device_descriptor, None)
             .await
-            .unwrap();
+            .map_err(Error::RequestDevice)?;
 
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps
@@ -46,7 +75,7 @@ impl GpuState {
             .copied()
             .find(TextureFormat::is_srgb)
             .unwrap_or(surface_caps.formats[0]);
-        let mut sample_count = 16;
+        let mut sample_count = args.msaa;
         while !adapter
             .get_texture_format_features(surface_format)
             .flags
@@ -54,17 +83,26 @@ impl GpuState {
         {
             sample_count /= 2;
         }
+        let present_mode = match args.present_mode.map(PresentMode::from) {
+            Some(mode) if !surface_caps.present_modes.contains(&mode) => {
+                println!("Present mode {mode:?} is not supported, falling back to Fifo");
+                PresentMode::Fifo
+            }
+            Some(mode) => mode,
+            None => PresentMode::AutoNoVsync,
+        };
         let size = window.inner_size();
         let surface_config = SurfaceConfiguration {
             usage: TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: PresentMode::AutoNoVsync,
+            present_mode,
             alpha_mode: surface_caps.alpha_modes[0],
             view_formats: vec![],
         };
         surface.configure(&device, &surface_config);
+        let timestamp_period = gpu_profile.then(|| queue.get_timestamp_period());
         let mut out = Self {
             device,
             queue,
@@ -72,11 +110,33 @@ impl GpuState {
             surface_config,
             msaa_texture_view: None,
             sample_count,
+            timestamp_period,
         };
         if sample_count > 1 {
             out.msaa_texture_view = Some(out.create_msaa_texture_view());
         }
-        out
+        Ok(out)
+    }
+    async fn request_adapter(
+        window: &Window,
+        backends: Backends,
+        power_preference: PowerPreference,
+        force_fallback_adapter: bool,
+    ) -> Result<Option<(Surface, Adapter)>, Error> {
+        let instance = Instance::new(InstanceDescriptor {
+            backends,
+            dx12_shader_compiler: Dx12Compiler::default(),
+        });
+
+        let surface = unsafe { instance.create_surface(window) }.map_err(Error::CreateSurface)?;
+
+        let request_adapter_options = RequestAdapterOptionsBase {
+            power_preference,
+            force_fallback_adapter,
+            compatible_surface: Some(&surface),
+        };
+        let adapter = instance.request_adapter(&request_adapter_options).await;
+        Ok(adapter.map(|adapter| (surface, adapter)))
     }
     fn create_msaa_texture_view(&self) -> TextureView {
         self.device
@@ -117,15 +177,18 @@ impl GpuState {
         &'a self,
         encoder: &'a mut CommandEncoder,
         output_texture: &'a TextureView,
-    ) -> RenderPass {
+        background: Background,
+    ) -> RenderPass<'a> {
         let mut color_attachment = RenderPassColorAttachment {
             view: output_texture,
             resolve_target: None,
             ops: Operations {
-                load: LoadOp::Clear(Color::BLACK),
+                load: LoadOp::Clear(background.into()),
                 store: true,
             },
         };
+        // The multisampled texture is cleared instead, and the clear color ends up in the output
+        // when it's resolved
         if self.sample_count > 1 {
             color_attachment.view = self.msaa_texture_view.as_ref().unwrap();
             color_attachment.resolve_target = Some(output_texture);
@@ -152,3 +215,122 @@ impl GpuState {
         Ok((surface_texture, texture_view))
     }
 }
+
+#[derive(Debug)]
+pub enum Error {
+    CreateSurface(CreateSurfaceError),
+    NoAdapter,
+    /// The adapter's limits are too low to draw the map
+    Limits(String),
+    RequestDevice(RequestDeviceError),
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::CreateSurface(e) => write!(f, "failed to create a surface: {e}"),
+            Error::NoAdapter => write!(f, "no compatible graphics adapter was found"),
+            Error::Limits(e) => write!(f, "the graphics adapter isn't capable enough: {e}"),
+            Error::RequestDevice(e) => write!(f, "failed to open the graphics device: {e}"),
+        }
+    }
+}
+impl std::error::Error for Error {}
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// The maximum number of MSAA samples per pixel (1 disables MSAA)
+    #[arg(default_value_t = 16, long, value_parser = clap::value_parser!(u32).range(1..=16))]
+    msaa: u32,
+    /// The graphics backend to render with (defaults to any available backend)
+    #[arg(long)]
+    backend: Option<Backend>,
+    /// Whether to prefer a low power or a high performance GPU
+    #[arg(default_value = "high", long)]
+    power: Power,
+    /// How frames are presented to the screen (defaults to vsync off where available)
+    #[arg(long)]
+    present_mode: Option<Present>,
+    /// Render with a software adapter, for machines without a GPU
+    #[arg(long)]
+    software: bool,
+    /// Log how long the GPU spends rendering each frame and the block textures, at the info level
+    /// (-v). Only works on adapters that support timestamp queries.
+    #[arg(long)]
+    gpu_profile: bool,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Backend {
+    Vulkan,
+    Metal,
+    Dx12,
+    Gl,
+}
+impl From<Backend> for Backends {
+    fn from(backend: Backend) -> Self {
+        match backend {
+            Backend::Vulkan => Backends::VULKAN,
+            Backend::Metal => Backends::METAL,
+            Backend::Dx12 => Backends::DX12,
+            Backend::Gl => Backends::GL,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Power {
+    Low,
+    High,
+}
+impl From<Power> for PowerPreference {
+    fn from(power: Power) -> Self {
+        match power {
+            Power::Low => PowerPreference::LowPower,
+            Power::High => PowerPreference::HighPerformance,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Present {
+    Fifo,
+    Mailbox,
+    Immediate,
+}
+impl From<Present> for PresentMode {
+    fn from(present: Present) -> Self {
+        match present {
+            Present::Fifo => PresentMode::Fifo,
+            Present::Mailbox => PresentMode::Mailbox,
+            Present::Immediate => PresentMode::Immediate,
+        }
+    }
+}
+
+/// The color behind the map and the UI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Background {
+    Black,
+    White,
+    /// Only see-through if the window supports transparency, which is mostly useful for exports
+    Transparent,
+}
+impl Background {
+    pub const ALL: [Self; 3] = [Self::Black, Self::White, Self::Transparent];
+    pub fn name(self) -> &'static str {
+        match self {
+            Background::Black => "Black",
+            Background::White => "White",
+            Background::Transparent => "Transparent",
+        }
+    }
+}
+impl From<Background> for Color {
+    fn from(background: Background) -> Self {
+        match background {
+            Background::Black => Color::BLACK,
+            Background::White => Color::WHITE,
+            Background::Transparent => Color::TRANSPARENT,
+        }
+    }
+}
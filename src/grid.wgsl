@@ -0,0 +1,63 @@
+// Draws lines along the boundaries of the subnets of a given prefix length over the whole map.
+// Even prefixes are squares on the hilbert curve, so the boundaries fall on a regular grid.
+
+struct ViewUniform {
+    pan: vec2<f32>,
+    zoom: vec2<f32>,
+    palette: u32,
+    binary: u32,
+    hide_responders: u32,
+    hide_timeouts: u32,
+    grid_prefix: u32,
+    density: u32,
+    diff: u32,
+    cell_size: f32,
+    brightness: f32,
+    gamma: f32,
+    max_latency: f32
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) coords: vec2<f32>
+}
+
+@group(0) @binding(0)
+var<uniform> view: ViewUniform;
+
+@vertex
+fn vs_grid(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var vertex = vertex_from_index(vertex_index);
+    var out: VertexOutput;
+    // the map is 2^16 addresses wide
+    out.coords = (vertex + 1.) / 2. * f32(1u << 16u);
+    out.clip_position = vec4<f32>((vertex + view.pan) * view.zoom, 1., 1.);
+    return out;
+}
+
+@fragment
+fn fs_grid(in: VertexOutput) -> @location(0) vec4<f32> {
+    if view.grid_prefix == 0u {
+        discard;
+    }
+    let cell_width = f32(1u << ((32u - view.grid_prefix) / 2u));
+    let cells = in.coords / cell_width;
+    let cells_per_pixel = fwidth(cells);
+    // distance to the nearest boundary, in pixels
+    let dist = abs(fract(cells - 0.5) - 0.5) / cells_per_pixel;
+    let line = 1. - clamp(min(dist.x, dist.y), 0., 1.);
+    // fade the lines out as the cells get too small to make out
+    let fade = clamp((1. / cells_per_pixel.x - 4.) / 8., 0., 1.);
+    let alpha = line * fade * 0.3;
+    return vec4<f32>(vec3<f32>(0.5) * alpha, alpha);
+}
+
+fn vertex_from_index(index: u32) -> vec2<f32> {
+    switch index {
+        case 0u: {return vec2<f32>(-1., -1.);}
+        case 1u, 3u: {return vec2<f32>(-1., 1.);}
+        case 2u, 4u: {return vec2<f32>(1., -1.);}
+        case 5u: {return vec2<f32>(1., 1.);}
+        default: {return vec2<f32>(0., 0.);}
+    }
+}
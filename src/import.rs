@@ -0,0 +1,223 @@
+use ipnet::Ipv4Net;
+use iprange::IpRange;
+use std::{collections::HashMap, net::Ipv4Addr, path::PathBuf};
+use tokio::{
+    fs::OpenOptions,
+    io::{AsyncWriteExt, BufWriter},
+};
+
+use crate::ping_file::{path_from_range, Header, Precision, ResultWriter};
+
+/// Ranges that aren't given are made of the /24s that the results are in
+const DERIVED_PREFIX_LEN: u8 = 24;
+
+pub async fn main(args: Args) {
+    if let Err(e) = import(args).await {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+}
+
+async fn import(args: Args) -> Result<(), String> {
+    let input = tokio::fs::read_to_string(&args.input)
+        .await
+        .map_err(|e| format!("{}: {e}", args.input.display()))?;
+    let mut results = HashMap::new();
+    for (i, line) in input.lines().enumerate() {
+        let parsed = match args.format {
+            Format::Fping => parse_fping_line(line),
+            Format::Masscan => parse_masscan_line(line),
+        };
+        let parsed = parsed.map_err(|e| format!("{}:{}: {e}", args.input.display(), i + 1))?;
+        let Some((addr, rtt)) = parsed else {
+            continue;
+        };
+        // An address that shows up more than once keeps its fastest reply
+        let best = results.entry(addr).or_insert(rtt);
+        if rtt >= 0. && (*best < 0. || rtt < *best) {
+            *best = rtt;
+        }
+    }
+    if results.is_empty() {
+        return Err(format!("{} has no results", args.input.display()));
+    }
+
+    let mut range = IpRange::new();
+    if args.subnets.is_empty() {
+        for addr in results.keys() {
+            range.add(Ipv4Net::new(*addr, DERIVED_PREFIX_LEN).unwrap().trunc());
+        }
+    } else {
+        for subnet in &args.subnets {
+            let net = subnet
+                .parse::<Ipv4Net>()
+                .map_err(|e| format!("invalid subnet {subnet:?}: {e}"))?;
+            range.add(net);
+        }
+    }
+    range.simplify();
+    // Every address in the range gets a result, so there's nowhere for the network and broadcast
+    // addresses of the other tool's results to go missing
+    let header = Header {
+        range,
+        all_addresses: true,
+        run_length: args.run_length,
+        precision: Precision::F32,
+        stride: 1,
+    };
+    let path = match args.out {
+        Some(out) => out,
+        None => path_from_range(header.range.clone()).unwrap(),
+    };
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .await
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut buf_writer = BufWriter::new(file);
+    buf_writer.write_all(&header.to_bytes()).await.unwrap();
+
+    // Addresses that the other tool has no result for are written as timeouts
+    let mut writer = ResultWriter::new(buf_writer, &header);
+    let mut num_imported = 0;
+    for addr in header.addresses() {
+        let rtt = match results.get(&addr) {
+            Some(&rtt) => {
+                num_imported += 1;
+                rtt
+            }
+            None => -1.,
+        };
+        writer.write(rtt).await.unwrap();
+    }
+    writer.shutdown().await.unwrap();
+
+    println!("Imported {num_imported} results into {}", path.display());
+    let num_left_out = results.len() - num_imported;
+    if num_left_out > 0 {
+        println!("{num_left_out} results were left out because they're outside of the range");
+    }
+    Ok(())
+}
+
+/// Parse a line of `fping -C` output, like `10.0.0.1 : 1.23 0.98 -`, into the fastest of its
+/// ping times in seconds, or -1 if every ping timed out. Summary lines and blank lines are skipped.
+fn parse_fping_line(line: &str) -> Result<Option<(Ipv4Addr, f32)>, String> {
+    let Some((addr, rtts)) = line.split_once(" : ") else {
+        return Ok(None);
+    };
+    let addr = addr
+        .trim()
+        .parse::<Ipv4Addr>()
+        .map_err(|e| format!("invalid address {addr:?}: {e}"))?;
+    let mut best = -1f32;
+    for rtt in rtts.split_whitespace() {
+        // fping prints a dash for a ping that timed out
+        if rtt == "-" {
+            continue;
+        }
+        let ms = rtt
+            .parse::<f32>()
+            .map_err(|e| format!("invalid ping time {rtt:?}: {e}"))?;
+        let secs = ms / 1e3;
+        if best < 0. || secs < best {
+            best = secs;
+        }
+    }
+    Ok(Some((addr, best)))
+}
+
+/// Parse a host out of a line of `masscan -oJ` output, where each host is an object on a line of
+/// its own. masscan doesn't measure ping times, so every host it found is given a time of 0.
+fn parse_masscan_line(line: &str) -> Result<Option<(Ipv4Addr, f32)>, String> {
+    // The objects are in an array, which older versions of masscan leave a trailing comma in
+    let line = line.trim().trim_end_matches(',');
+    if line.is_empty() || line == "[" || line == "]" {
+        return Ok(None);
+    }
+    let host = serde_json::from_str::<MasscanHost>(line).map_err(|e| e.to_string())?;
+    Ok(Some((host.ip, 0.)))
+}
+
+#[derive(serde::Deserialize)]
+struct MasscanHost {
+    ip: Ipv4Addr,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Format {
+    /// The output of `fping -C <count> -q`, one `address : times` line per host
+    Fping,
+    /// The JSON output of `masscan -oJ`
+    Masscan,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// The output of the other tool
+    input: PathBuf,
+    /// Which tool the output is from
+    #[arg(long)]
+    format: Format,
+    /// The subnets that the ping file covers (defaults to the /24s with results)
+    #[arg(short, long)]
+    subnets: Vec<String>,
+    /// Store each run of timeouts as a single result, which makes sparse results much smaller
+    #[arg(long)]
+    run_length: bool,
+    /// The file to write the results to (defaults to a name derived from the subnets)
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fping_keeps_the_fastest_time() {
+        let addr = Ipv4Addr::new(10, 0, 0, 1);
+        assert_eq!(
+            parse_fping_line("10.0.0.1 : 1.50 - 0.50 2.00"),
+            Ok(Some((addr, 0.0005)))
+        );
+        assert_eq!(
+            parse_fping_line("10.0.0.1     : - - 3.00"),
+            Ok(Some((addr, 0.003)))
+        );
+        assert_eq!(parse_fping_line("10.0.0.1 : - - -"), Ok(Some((addr, -1.))));
+    }
+
+    #[test]
+    fn fping_skips_other_lines() {
+        for line in ["", "   ", "10.0.0.1 is unreachable", "summary: 3/3 sent"] {
+            assert_eq!(parse_fping_line(line), Ok(None), "{line:?}");
+        }
+    }
+
+    #[test]
+    fn fping_rejects_bad_lines() {
+        assert!(parse_fping_line("10.0.0.256 : 1.00").is_err());
+        assert!(parse_fping_line("example.com : 1.00").is_err());
+        assert!(parse_fping_line("10.0.0.1 : 1.00 fast").is_err());
+    }
+
+    #[test]
+    fn masscan_skips_the_array_brackets() {
+        for line in ["[", "]", "", "  [  "] {
+            assert_eq!(parse_masscan_line(line), Ok(None), "{line:?}");
+        }
+    }
+
+    #[test]
+    fn masscan_hosts_have_a_time_of_0() {
+        let line = r#"{   "ip": "10.0.0.7",   "timestamp": "1686000000", "ports": [ {"port": 0, "proto": "icmp", "status": "open"} ] }"#;
+        let expected = Ok(Some((Ipv4Addr::new(10, 0, 0, 7), 0.)));
+        assert_eq!(parse_masscan_line(line), expected);
+        // every object but the last has a trailing comma
+        assert_eq!(parse_masscan_line(&format!("{line},")), expected);
+        assert!(parse_masscan_line(r#"{"ip": "10.0.0.300"}"#).is_err());
+        assert!(parse_masscan_line("{not json}").is_err());
+    }
+}
@@ -0,0 +1,45 @@
+use itertools::Itertools;
+use std::path::PathBuf;
+
+use crate::{metadata::Metadata, ping_file, summary::Summary};
+
+pub async fn main(args: Args) {
+    let result = async {
+        let (header, _) = ping_file::open(&args.file).await?;
+        let summary = Summary::from_file(&args.file, args.num_slowest).await?;
+        let metadata = Metadata::load(&args.file)?;
+        std::io::Result::Ok((header, summary, metadata))
+    };
+    let (header, summary, metadata) = match result.await {
+        Ok(info) => info,
+        Err(e) => {
+            eprintln!("Error: {}: {e}", args.file.display());
+            std::process::exit(1);
+        }
+    };
+    if args.json {
+        println!("{}", summary.to_json());
+        return;
+    }
+    println!("range: {}", header.range.iter().join(", "));
+    if summary.num_results < summary.num_addrs {
+        let perc_done = summary.num_results as f64 / summary.num_addrs as f64 * 100.;
+        println!("incomplete: {perc_done:.3}% done");
+    }
+    print!("{}", summary.to_text());
+    if let Some(metadata) = metadata {
+        print!("{}", metadata.to_text());
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// The ping file to summarize
+    file: PathBuf,
+    /// The number of slowest responders to list
+    #[arg(default_value_t = 0, long)]
+    num_slowest: usize,
+    /// Print the summary as JSON
+    #[arg(long)]
+    json: bool,
+}
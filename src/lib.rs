@@ -0,0 +1,8 @@
+//! The engine behind `pinger ping`, for scanning ranges of addresses from other programs
+
+mod engine;
+
+pub use engine::{
+    addresses, addresses_with_offsets, ConfigError, Packet, PacketKind, PingConfig,
+    PingConfigBuilder, PingEngine, PingResult, State, PERMISSION_HINT,
+};
@@ -1,17 +1,40 @@
-use clap::Parser;
-use tracing::Level;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use tracing::level_filters::LevelFilter;
 use tracing_chrome::ChromeLayerBuilder;
-use tracing_subscriber::prelude::*;
+use tracing_subscriber::{prelude::*, EnvFilter};
 
+mod color;
+mod config;
+mod db;
 mod gpu;
+mod import;
+mod info;
+mod merge;
+mod metadata;
+mod metrics;
+mod pcap;
 mod ping;
+mod ping_file;
 mod ping_map;
+mod reverse_dns;
+mod summary;
+mod theme;
 mod ui;
+mod verify;
 mod wgpu_ext;
+mod window_geometry;
 
 #[tokio::main]
 async fn main() {
-    let args = Args::parse();
+    // Flags that aren't given default to the config file, and then to their built-in defaults
+    let command = match config::with_defaults(Args::command()) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+    let args = Args::from_arg_matches(&command.get_matches()).unwrap_or_else(|e| e.exit());
     if args.trace {
         let (chrome_layer, _guard) = ChromeLayerBuilder::new()
             .include_args(true)
@@ -19,14 +42,32 @@ async fn main() {
             .build();
         tracing_subscriber::registry().with(chrome_layer).init();
     } else {
+        let default_level = match args.verbose {
+            0 => LevelFilter::WARN,
+            1 => LevelFilter::INFO,
+            2 => LevelFilter::DEBUG,
+            _ => LevelFilter::TRACE,
+        };
+        // Directives from RUST_LOG take precedence over the level set by the verbosity flag
+        let filter = EnvFilter::builder()
+            .with_default_directive(default_level.into())
+            .from_env_lossy();
         tracing_subscriber::FmtSubscriber::builder()
-            .with_max_level(Level::WARN)
+            .with_env_filter(filter)
             .finish()
             .init();
     }
     match args.subcommand {
-        Subcommand::Ping(args) => ping::main(args).await,
-        Subcommand::Gui => ui::main().await,
+        Subcommand::Ping(args) => ping::main(*args).await,
+        Subcommand::Gui(args) => ui::main(args).await,
+        Subcommand::Info(args) => info::main(args).await,
+        Subcommand::Verify(args) => verify::main(args).await,
+        Subcommand::Merge(args) => merge::main(args).await,
+        Subcommand::Import(args) => import::main(args).await,
+        Subcommand::Completions { shell } => {
+            let name = env!("CARGO_PKG_NAME");
+            clap_complete::generate(shell, &mut Args::command(), name, &mut std::io::stdout());
+        }
     }
 }
 #[derive(Parser, Debug)]
@@ -36,12 +77,34 @@ struct Args {
     /// Generate a trace file
     #[arg(short, long)]
     trace: bool,
+    /// Increase the logging verbosity (can be repeated)
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
 }
 
 #[derive(Debug, clap::Subcommand)]
 enum Subcommand {
     /// Ping a provided range of addresses and save the response durations to a file
-    Ping(ping::Args),
+    ///
+    /// On Unix, sending the process SIGUSR1 pauses or resumes the scan, and SIGUSR2 resumes it.
+    ///
+    /// Defaults for the flags can be set in the [ping] table of config.toml in the config
+    /// directory, like ~/.config/pinger/config.toml, by their long names, like `concurrency =
+    /// 5000`. Flags given on the command line take precedence over the environment variables in
+    /// their help, which take precedence over the config file, which takes precedence over the
+    /// built-in defaults.
+    Ping(Box<ping::Args>),
     /// Open a GUI for viewing ping files
-    Gui,
+    Gui(ui::Args),
+    /// Summarize the results in a ping file
+    Info(info::Args),
+    /// Check a ping file for truncated or corrupt results
+    Verify(verify::Args),
+    /// Combine ping files for separate subnets into one file
+    Merge(merge::Args),
+    /// Build a ping file from the output of another scanner, like fping or masscan
+    Import(import::Args),
+    /// Print a script that completes the subcommands and flags in the given shell
+    #[command(hide = true)]
+    Completions { shell: clap_complete::Shell },
 }
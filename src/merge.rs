@@ -0,0 +1,137 @@
+use ipnet::Ipv4Net;
+use iprange::IpRange;
+use std::path::PathBuf;
+use tokio::{
+    fs::OpenOptions,
+    io::{AsyncWriteExt, BufWriter},
+};
+
+use crate::ping_file::{path_from_range, Header, MappedFile, Precision};
+
+pub async fn main(args: Args) {
+    if let Err(e) = merge(args).await {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+}
+
+async fn merge(args: Args) -> Result<(), String> {
+    let mut inputs = vec![];
+    for path in &args.files {
+        let file = MappedFile::open(path)
+            .await
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        inputs.push(Input {
+            path: path.clone(),
+            file,
+            offset: 0,
+        });
+    }
+
+    // Every address in the merged range has to come from exactly one of the files
+    let all_addresses = inputs[0].file.header().all_addresses;
+    for (i, a) in inputs.iter().enumerate() {
+        // Every address in a merged range would be pinged, which isn't the case with a stride
+        if a.file.header().stride != 1 {
+            return Err(format!(
+                "{} only has every {}th address, so it can't be merged",
+                a.path.display(),
+                a.file.header().stride
+            ));
+        }
+        if a.file.header().all_addresses != all_addresses {
+            return Err(format!(
+                "{} and {} don't agree on whether network and broadcast addresses were pinged",
+                inputs[0].path.display(),
+                a.path.display()
+            ));
+        }
+        for b in &inputs[i + 1..] {
+            let overlap = a.file.header().range.intersect(&b.file.header().range);
+            if !overlap.is_empty() {
+                return Err(format!(
+                    "{} and {} both have results for {}",
+                    a.path.display(),
+                    b.path.display(),
+                    overlap.iter().next().unwrap()
+                ));
+            }
+        }
+    }
+
+    // The subnets are kept as they are rather than simplified, since combining two subnets into a
+    // bigger one would add network and broadcast addresses that neither file has results for.
+    let mut range = IpRange::new();
+    for input in &inputs {
+        for net in input.file.header().range.iter() {
+            range.add(net);
+        }
+    }
+    let header = Header {
+        range,
+        all_addresses,
+        run_length: false,
+        precision: Precision::F32,
+        stride: 1,
+    };
+    let path = match args.out {
+        Some(out) => out,
+        None => path_from_range(header.range.clone()).unwrap(),
+    };
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .await
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut buf_writer = BufWriter::new(file);
+    buf_writer.write_all(&header.to_bytes()).await.unwrap();
+
+    // Results have to be contiguous, so the merged file ends at the first address that doesn't
+    // have a result yet.
+    let mut num_written = 0;
+    for addr in header.addresses() {
+        let net = Ipv4Net::from(addr);
+        let input = inputs
+            .iter_mut()
+            .find(|input| input.file.header().range.contains(&net))
+            .unwrap();
+        let Some(rtt) = input.file.get(input.offset) else {
+            break;
+        };
+        input.offset += 1;
+        buf_writer.write_f32(rtt).await.unwrap();
+        num_written += 1;
+    }
+    buf_writer.flush().await.unwrap();
+
+    println!("Wrote {num_written} results to {}", path.display());
+    let num_left_out = inputs
+        .iter()
+        .map(|input| input.file.num_results() - input.offset)
+        .sum::<u64>();
+    if num_left_out > 0 {
+        println!(
+            "{num_left_out} results were left out because they come after an address that \
+             hasn't been pinged yet"
+        );
+    }
+    Ok(())
+}
+
+struct Input {
+    path: PathBuf,
+    file: MappedFile,
+    /// The number of results from this file that have been written so far
+    offset: u64,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// The ping files to merge, which must not overlap
+    #[arg(required = true, num_args = 2..)]
+    files: Vec<PathBuf>,
+    /// The file to write the merged results to (defaults to a name derived from the merged subnets)
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
@@ -0,0 +1,82 @@
+use std::{
+    fmt::Write,
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// When a scan ran and with what settings, which is written to a JSON file next to the ping file
+/// so scans taken at different times can be told apart
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Metadata {
+    /// The version of pinger that ran the scan
+    pub version: String,
+    /// When the scan started, in seconds since the Unix epoch
+    pub start_time: u64,
+    /// When the scan finished, or `None` if it's still running or was interrupted
+    pub end_time: Option<u64>,
+    pub timeout_secs: u64,
+    pub num_retries: usize,
+    pub speed_factor: usize,
+    pub concurrency: Option<usize>,
+    pub rate: Option<f64>,
+}
+impl Metadata {
+    /// The path of the sidecar for the ping file at the given path
+    pub fn path(ping_path: impl AsRef<Path>) -> PathBuf {
+        let mut path = ping_path.as_ref().as_os_str().to_owned();
+        path.push(".meta.json");
+        path.into()
+    }
+    /// Load the sidecar for a ping file, or `None` if it doesn't have one
+    pub fn load(ping_path: impl AsRef<Path>) -> io::Result<Option<Self>> {
+        let json = match std::fs::read_to_string(Self::path(ping_path)) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let metadata = serde_json::from_str(&json)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Some(metadata))
+    }
+    pub async fn save(&self, ping_path: impl AsRef<Path>) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self).unwrap();
+        tokio::fs::write(Self::path(ping_path), json).await
+    }
+    /// The current time in the same form as `start_time` and `end_time`
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+    pub fn to_text(&self) -> String {
+        let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".into());
+        let mut text = String::new();
+        writeln!(text, "scanned by pinger {}", self.version).unwrap();
+        writeln!(text, "started: {} (unix time)", self.start_time).unwrap();
+        match self.end_time {
+            Some(end_time) => writeln!(
+                text,
+                "finished: {end_time} (unix time), after {}s",
+                end_time.saturating_sub(self.start_time)
+            )
+            .unwrap(),
+            None => writeln!(text, "finished: no").unwrap(),
+        }
+        writeln!(
+            text,
+            "timeout/retries/speed factor: {}s/{}/{}",
+            self.timeout_secs, self.num_retries, self.speed_factor
+        )
+        .unwrap();
+        writeln!(
+            text,
+            "concurrency/rate: {}/{}",
+            or_dash(self.concurrency.map(|c| c.to_string())),
+            or_dash(self.rate.map(|r| format!("{r}/s")))
+        )
+        .unwrap();
+        text
+    }
+}
@@ -0,0 +1,79 @@
+use std::{sync::Arc, time::Instant};
+
+use pinger::State;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    time::{timeout, Duration},
+};
+
+/// How long a client has to send its request before it's dropped, so a stalled client can't hold
+/// up the next scrape
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait after failing to accept a connection, since errors like running out of file
+/// descriptors would otherwise be retried in a busy loop that takes time away from the scan
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+/// Answer every request on the listener with the scan's metrics, whatever the path. Requests are
+/// answered one at a time, which is plenty for a scraper.
+pub async fn serve(listener: TcpListener, state: Arc<State>) {
+    // The rate is measured between scrapes, like `stats_printer` does between prints
+    let mut last_time = Instant::now();
+    let mut last_value = state.num_done();
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                tracing::warn!("Failed to accept a metrics connection: {e}");
+                tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                continue;
+            }
+        };
+        // The request itself doesn't matter, but it has to be read before answering it
+        let mut buf = [0; 1024];
+        if !matches!(
+            timeout(REQUEST_TIMEOUT, stream.read(&mut buf)).await,
+            Ok(Ok(_))
+        ) {
+            continue;
+        }
+        let now = Instant::now();
+        let done = state.num_done();
+        let rate = done.saturating_sub(last_value) as f64 / (now - last_time).as_secs_f64();
+        last_time = now;
+        last_value = done;
+        let mut body = String::new();
+        // There's no total when the addresses are read from stdin
+        if let Some(total) = state.total() {
+            body += &format!(
+                "# HELP pinger_addresses_total The number of addresses in the range\n\
+                 # TYPE pinger_addresses_total gauge\n\
+                 pinger_addresses_total {total}\n"
+            );
+        }
+        body += &format!(
+            "# HELP pinger_addresses_done The number of addresses pinged so far in this pass\n\
+             # TYPE pinger_addresses_done gauge\n\
+             pinger_addresses_done {done}\n\
+             # HELP pinger_active The number of pings in flight\n\
+             # TYPE pinger_active gauge\n\
+             pinger_active {}\n\
+             # HELP pinger_rate The pings per second since the last scrape\n\
+             # TYPE pinger_rate gauge\n\
+             pinger_rate {rate}\n",
+            state.num_running(),
+        );
+        let response = format!(
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/plain; version=0.0.4\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\
+             \r\n\
+             {body}",
+            body.len()
+        );
+        // A scraper that hangs up early just misses this scrape
+        let _ = stream.write_all(response.as_bytes()).await;
+        let _ = stream.shutdown().await;
+    }
+}
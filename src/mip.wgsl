@@ -0,0 +1,46 @@
+// Builds each mip level of the atlas pages from the level below it. Instead of averaging, each texel
+// keeps the most interesting of the four texels it covers, the same way `load_texel` in shader.wgsl
+// picks between texels, so lone responders still show up when zoomed out.
+
+@group(0) @binding(0)
+var source: texture_2d<u32>;
+
+@vertex
+fn vs_mip(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(vertex_from_index(vertex_index), 0., 1.);
+}
+
+@fragment
+fn fs_mip(@builtin(position) position: vec4<f32>) -> @location(0) u32 {
+    // the position is in texels of the level being written, which is half the size of the source
+    let coords = vec2<i32>(position.xy) * 2;
+    var best = 0u;
+    for (var i = 0; i < 4; i += 1) {
+        let texel = textureLoad(source, coords + vec2<i32>(i % 2, i / 2), 0).x;
+        if texel_rank(texel) > texel_rank(best) {
+            best = texel;
+        }
+    }
+    return best;
+}
+
+// the same as `texel_rank` in shader.wgsl
+fn texel_rank(texel: u32) -> u32 {
+    if texel == 0u {
+        return 0u;
+    }
+    if texel == 255u {
+        return 1u;
+    }
+    return texel + 1u;
+}
+
+fn vertex_from_index(index: u32) -> vec2<f32> {
+    switch index {
+        case 0u: {return vec2<f32>(-1., -1.);}
+        case 1u, 3u: {return vec2<f32>(-1., 1.);}
+        case 2u, 4u: {return vec2<f32>(1., -1.);}
+        case 5u: {return vec2<f32>(1., 1.);}
+        default: {return vec2<f32>(0., 0.);}
+    }
+}
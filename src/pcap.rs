@@ -0,0 +1,126 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::Ipv4Addr,
+    path::Path,
+    thread::JoinHandle,
+    time::UNIX_EPOCH,
+};
+
+use pinger::{Packet, PacketKind};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// The packets are bare IPv4, with no link layer header
+const LINKTYPE_RAW: u32 = 101;
+const SNAPLEN: u32 = 65535;
+/// Every ping is sent with this TTL as far as the capture is concerned, since the real one isn't known
+const TTL: u8 = 64;
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+/// Writes the packets of a scan to a pcap file on a thread of its own, so the scan never waits on it
+pub struct PcapWriter {
+    tx: UnboundedSender<Packet>,
+    handle: JoinHandle<io::Result<()>>,
+}
+impl PcapWriter {
+    /// Create the file at the given path. The packets are written as coming from and going to
+    /// `local`, with the given type of service.
+    pub fn create(path: &Path, local: Ipv4Addr, tos: u8) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        // the header of a microsecond pcap, which readers can tell the byte order of by the magic
+        writer.write_all(&0xa1b2c3d4u32.to_le_bytes())?;
+        writer.write_all(&2u16.to_le_bytes())?;
+        writer.write_all(&4u16.to_le_bytes())?;
+        writer.write_all(&0i32.to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&SNAPLEN.to_le_bytes())?;
+        writer.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        let handle = std::thread::spawn(move || write_packets(writer, rx, local, tos));
+        Ok(Self { tx, handle })
+    }
+    /// Where the engine sends the packets to be written
+    pub fn sender(&self) -> UnboundedSender<Packet> {
+        self.tx.clone()
+    }
+    /// Wait for the packets to be written. Every other sender has to have been dropped by now,
+    /// which happens when the engine is.
+    pub fn finish(self) -> io::Result<()> {
+        drop(self.tx);
+        self.handle.join().unwrap()
+    }
+}
+
+fn write_packets(
+    mut writer: BufWriter<File>,
+    mut rx: UnboundedReceiver<Packet>,
+    local: Ipv4Addr,
+    tos: u8,
+) -> io::Result<()> {
+    // Wait for a packet, then write it along with everything else that's queued up by then. The
+    // file is written through whenever the writer has caught up, so it can be opened during the
+    // scan.
+    while let Some(first) = rx.blocking_recv() {
+        write_packet(&mut writer, first, local, tos)?;
+        while let Ok(packet) = rx.try_recv() {
+            write_packet(&mut writer, packet, local, tos)?;
+        }
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+fn write_packet(
+    writer: &mut impl Write,
+    packet: Packet,
+    local: Ipv4Addr,
+    tos: u8,
+) -> io::Result<()> {
+    let time = packet.time.duration_since(UNIX_EPOCH).unwrap();
+    let bytes = encode(packet, local, tos);
+    writer.write_all(&(time.as_secs() as u32).to_le_bytes())?;
+    writer.write_all(&time.subsec_micros().to_le_bytes())?;
+    // the packet is all there, so its captured length is its length
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(&bytes)
+}
+
+/// Build the IPv4 packet of an echo request or reply with no payload, like the scan sends
+fn encode(packet: Packet, local: Ipv4Addr, tos: u8) -> [u8; 28] {
+    let (kind, source, destination) = match packet.kind {
+        PacketKind::Request => (ICMP_ECHO_REQUEST, local, packet.addr),
+        PacketKind::Reply => (ICMP_ECHO_REPLY, packet.addr, local),
+    };
+    let mut bytes = [0; 28];
+    // version 4 with a 5 word header
+    bytes[0] = 0x45;
+    bytes[1] = tos;
+    bytes[2..4].copy_from_slice(&28u16.to_be_bytes());
+    bytes[8] = TTL;
+    // ICMP
+    bytes[9] = 1;
+    bytes[12..16].copy_from_slice(&source.octets());
+    bytes[16..20].copy_from_slice(&destination.octets());
+    let header_checksum = checksum(&bytes[..20]);
+    bytes[10..12].copy_from_slice(&header_checksum.to_be_bytes());
+    bytes[20] = kind;
+    bytes[24..26].copy_from_slice(&packet.identifier.to_be_bytes());
+    bytes[26..28].copy_from_slice(&packet.sequence.to_be_bytes());
+    let icmp_checksum = checksum(&bytes[20..]);
+    bytes[22..24].copy_from_slice(&icmp_checksum.to_be_bytes());
+    bytes
+}
+
+/// The internet checksum of an even number of bytes
+fn checksum(bytes: &[u8]) -> u16 {
+    let mut sum = bytes
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], word[1]]) as u32)
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
@@ -1,213 +1,1327 @@
-use ipnet::Ipv4Net;
+use async_compression::tokio::write::GzipEncoder;
+use ipnet::{Ipv4Net, Ipv4Subnets};
 use iprange::IpRange;
+use itertools::Itertools;
+use pinger::{PingConfig, PingEngine, PingResult, State, PERMISSION_HINT};
 use std::{
-    fmt::Write,
-    io::SeekFrom,
-    path::PathBuf,
-    sync::{
-        atomic::{AtomicU64, AtomicUsize, Ordering},
-        Arc,
-    },
-    time::{Duration, Instant},
+    io::{self, SeekFrom},
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::{
     fs::{File, OpenOptions},
-    io::{AsyncSeekExt, AsyncWriteExt, BufWriter},
-    sync::mpsc::UnboundedReceiver,
-    task::JoinHandle,
+    io::{AsyncBufReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter},
+    net::TcpListener,
+    sync::mpsc,
+    time::MissedTickBehavior,
 };
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+use tracing::Instrument;
 
-const DATA_SIZE: u64 = std::mem::size_of::<f32>() as u64;
+use crate::{
+    db::DbWriter,
+    metadata::Metadata,
+    metrics,
+    pcap::PcapWriter,
+    ping_file::{self, path_from_range, Header, MappedFile, Precision, ResultWriter, DATA_SIZE},
+    summary::{Slowest, Summary},
+};
 
-pub async fn main(args: Args) {
-    // Construct the collection of subnets from the cli arg
-    let mut range = IpRange::<Ipv4Net>::new();
-    for net_string in args.subnets {
-        range.add(net_string.parse().unwrap());
+/// `println!` for anything but the results, which goes to stderr instead when the results are
+/// streamed to stdout
+macro_rules! status {
+    ($stream:expr, $($arg:tt)*) => {
+        if $stream {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+const DEFAULT_SUBNETS: [&str; 2] = ["142.244.0.0/16", "129.128.0.0/16"];
+/// How many addresses from stdin can be waiting to be pinged before reading stops to let the scan
+/// catch up
+const STDIN_CHANNEL_CAPACITY: usize = 1 << 16;
+/// The concurrency that `--auto-concurrency` starts from, unless `--concurrency` is given
+const INITIAL_AUTO_CONCURRENCY: usize = 1000;
+/// How often `--auto-concurrency` measures the rate and adjusts the concurrency
+const AUTO_CONCURRENCY_INTERVAL: Duration = Duration::from_secs(2);
+/// `--auto-concurrency` moves this far towards the concurrency that would hit the target rate, on
+/// a log scale, so the noise in the rate doesn't make it swing back and forth
+const AUTO_CONCURRENCY_GAIN: f64 = 0.5;
+const MAX_AUTO_CONCURRENCY: usize = 1 << 20;
+
+pub async fn main(mut args: Args) {
+    if args.stdin {
+        return scan_stdin(args).await;
     }
 
-    // Open (or create) the file that does/will contain the data
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open(path_from_range(range.clone()).unwrap())
-        .await
-        .unwrap();
+    // Construct the collection of subnets to scan from the cli args. Addresses that were named
+    // explicitly are all pinged, which is then stored in the header like --all-addresses.
+    let range = match range_from_args(&args).await {
+        Ok((range, explicit)) => {
+            args.all_addresses |= explicit;
+            range
+        }
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+    // A range with nothing left in it would still get a file and a header, so stop before either
+    if let Err(e) = check_not_empty(&range, &args) {
+        status!(args.stream, "{e}");
+        return;
+    }
+
+    // Resolve the path of the file that does/will contain the data. The range is stored in the
+    // header, so the viewer doesn't depend on the filename when one is given explicitly.
+    let path = match args.out {
+        Some(ref out) => out.clone(),
+        None => {
+            let path = args.out_dir.join(path_from_range(range.clone()).unwrap());
+            if args.compress {
+                let mut name = path.into_os_string();
+                name.push(".gz");
+                name.into()
+            } else {
+                path
+            }
+        }
+    };
+    let compressed = ping_file::is_compressed(&path);
+    let header = Header {
+        range,
+        all_addresses: args.all_addresses,
+        run_length: args.run_length,
+        precision: args.precision,
+        stride: args.stride,
+    };
+
+    // Files that already have results but no header were written before headers existed. These
+    // are resumed without adding a header, so that the existing results stay where they are.
+    // When rotating, every pass goes to a new file, so there's nothing to resume.
+    let file_len = if args.rotate {
+        0
+    } else {
+        tokio::fs::metadata(&path).await.map_or(0, |m| m.len())
+    };
+    let legacy = !compressed
+        && file_len > 0
+        && match Header::is_missing(&path).await {
+            Ok(missing) => missing,
+            Err(e) => {
+                eprintln!("Error: {}: {e}", path.display());
+                std::process::exit(1);
+            }
+        };
 
     // Compute the number of completed pings in the file by dividing by the size of each entry. If an
     // entry is only half written, we round down (using integer division) so that we overwrite it in
-    // this execution.
-    let num_done = file.metadata().await.unwrap().len() / DATA_SIZE;
-
-    // Seek forward in the file to the entry after the last complete entry.  If the file was just
-    // created then this entry will just be the start of the file.
-    file.seek(SeekFrom::Start(num_done * DATA_SIZE))
-        .await
-        .unwrap();
+    // this execution. If the file doesn't exist yet, then nothing is done. Files with a header are
+    // opened to do this, since compressed and run-length encoded files have to be expanded first.
+    // A file of another range would have its results resumed at the wrong addresses, so it's only
+    // started over with --force. Files without a header can only be checked against their length.
+    let mut start_over = false;
+    let num_done = if file_len == 0 {
+        0
+    } else if legacy {
+        let num_done = file_len / DATA_SIZE;
+        let num_addrs = header.addresses().count() as u64;
+        if num_done <= num_addrs {
+            num_done
+        } else if args.force {
+            start_over = true;
+            0
+        } else {
+            eprintln!(
+                "Error: {} has {num_done} results, but the range only has {num_addrs} addresses, \
+                 so it's a scan of a different range. Pass --force to start it over",
+                path.display()
+            );
+            std::process::exit(1);
+        }
+    } else {
+        match MappedFile::open(&path).await {
+            // the last run was stopped before it had written the whole header, so there are no
+            // results to keep
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => 0,
+            Err(e) => {
+                eprintln!("Error: {}: {e}", path.display());
+                std::process::exit(1);
+            }
+            Ok(file) => {
+                // The range isn't simplified first, since a merged file's adjacent subnets each
+                // leave out their own network and broadcast addresses, which the simplified range
+                // would have results for
+                let file_range = &file.header().range;
+                if file_range.iter().eq(header.range.iter()) {
+                    check_resumable(&file, &header, &path);
+                    file.num_results()
+                } else if args.force {
+                    start_over = true;
+                    0
+                } else {
+                    eprintln!(
+                        "Error: {} is a scan of {}, not {}. Pass --force to start it over",
+                        path.display(),
+                        file_range.iter().join(", "),
+                        header.range.iter().join(", ")
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+    };
 
-    // Construct an iterator that will yield the remaining addresses to ping.
-    let addrs = range
-        .into_iter()
-        .flat_map(|net| net.hosts())
-        .skip(num_done as usize);
+    // Files without a header are resumed without any of the settings that came with headers, since
+    // those are stored in the header. One that's started over gets a header instead.
+    let legacy = legacy && !start_over;
+    let header_settings = [
+        (header.all_addresses, "--all-addresses"),
+        (header.run_length, "--run-length"),
+        (header.precision != Precision::F32, "--precision f16"),
+        (header.stride != 1, "--stride"),
+    ];
+    if let Some((_, flag)) = header_settings.iter().find(|(set, _)| legacy && *set) {
+        eprintln!(
+            "Error: {} has no header, so it can't be resumed with {flag}",
+            path.display()
+        );
+        std::process::exit(1);
+    }
 
     // Count the total number of addresses in the specified network range. Print the total number
     // and remaining number of addresses to screen.
-    let total_num_addrs = range.into_iter().flat_map(|net| net.hosts()).count();
-    println!("{total_num_addrs} addresses to ping in total");
-    println!("{num_done} addresses already in the file");
+    let total_num_addrs = header.addresses().count();
+    status!(args.stream, "{total_num_addrs} addresses to ping in total");
+    status!(args.stream, "{num_done} addresses already in the file");
 
-    // Construct the shared state struct and the pinger client struct.
-    let state = Arc::new(State::new(total_num_addrs as u64, num_done));
-    let client = Arc::new(surge_ping::Client::new(&surge_ping::Config::default()).unwrap());
+    // In a dry run, report where the results would go and exit before sending any pings.
+    if args.dry_run {
+        status!(
+            args.stream,
+            "Results would be written to {}",
+            path.display()
+        );
+        return;
+    }
+    // The file that's started over is kept under another name, in case it's still wanted
+    if start_over {
+        let old_path = rotated_path(&path);
+        status!(
+            args.stream,
+            "Starting {} over, since it's a scan of a different range. The old file was moved to {}",
+            path.display(),
+            old_path.display()
+        );
+        tokio::fs::rename(&path, &old_path).await.unwrap();
+    }
 
-    // Construct the channel that will be used to send ping results to the file writer.
-    let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<JoinHandle<Option<Duration>>>();
+    // Construct the ping engine before touching the file, since this fails if we don't have
+    // permission to send pings.
+    let pcap = open_pcap(&args);
+    let engine = engine_from_args(
+        &args,
+        Some(header.range.clone()),
+        header.stride,
+        pcap.as_ref(),
+    );
+    start_controls(&args, engine.state()).await;
 
-    // Spawn the file writer task, which is given the reciever end of the channel and the file handle
-    // wrapped in a BufWriter to speed up writes.
-    let buf_writer = BufWriter::with_capacity(4 * 100, file);
-    let file_writer_handle = tokio::spawn(file_writer(rx, buf_writer));
+    // Insert the results into a database as well as writing them to the file, along with what was
+    // scanned and how
+    let db = open_db(&args, header.range.iter().join(","), header.stride);
+
+    // Keep the slowest responders of the whole run for the stats printer to show as it goes
+    let slowest = new_slowest(&args);
+    let flush_interval = args.flush_interval.map(Duration::from_secs);
+
+    // Scan the range once, or repeatedly in watch mode. Every pass after the first starts over
+    // from the first address, either overwriting the file or writing to a new one.
+    let mut stats_printer_handle = None;
+    let mut num_done = num_done;
+    loop {
+        let pass_path = if args.rotate {
+            rotated_path(&path)
+        } else {
+            path.clone()
+        };
+        let results = engine.scan(num_done).map(|result| {
+            if let Some(ref db) = db {
+                db.insert(result);
+            }
+            record_slowest(&slowest, result);
+            result
+        });
+        // Spawn the stats printer task once the first pass has started, which is given a reference
+        // to the shared state and the update interval from the cli arg. In watch mode it keeps
+        // printing between passes.
+        stats_printer_handle.get_or_insert_with(|| {
+            let subnets = if args.per_subnet {
+                subnet_ends(&header)
+            } else {
+                vec![]
+            };
+            tokio::spawn(stats_printer(
+                engine.state().clone(),
+                Duration::from_secs(args.update_interval),
+                args.watch.is_none(),
+                subnets,
+                slowest.clone(),
+                args.stream,
+            ))
+        });
+        // Record when the pass started and with what settings, and then when it finished
+        let mut metadata = args.metadata.then(|| Metadata {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            start_time: Metadata::now(),
+            end_time: None,
+            timeout_secs: args.timeout,
+            num_retries: args.num_retries,
+            speed_factor: args.speed_factor,
+            concurrency: args.concurrency,
+            rate: args.rate,
+        });
+        if let Some(ref metadata) = metadata {
+            save_metadata(metadata, &pass_path).await;
+        }
+        if compressed {
+            scan_compressed(
+                &pass_path,
+                &header,
+                num_done,
+                results,
+                flush_interval,
+                args.stream,
+            )
+            .await;
+        } else {
+            scan(
+                &pass_path,
+                &header,
+                legacy,
+                num_done,
+                results,
+                flush_interval,
+                args.stream,
+            )
+            .await;
+        }
+        if let Some(ref mut metadata) = metadata {
+            metadata.end_time = Some(Metadata::now());
+            save_metadata(metadata, &pass_path).await;
+        }
+        // Summarize the whole file, including any results from before a resume.
+        let summary = Summary::from_file(&pass_path, args.num_slowest)
+            .await
+            .unwrap();
+        if args.json {
+            status!(args.stream, "{}", summary.to_json());
+        } else {
+            if engine.state().is_stopped() {
+                status!(
+                    args.stream,
+                    "Stopped early after {:?}, {} of {} addresses have been pinged",
+                    args.max_duration.unwrap(),
+                    summary.num_results,
+                    summary.num_addrs
+                );
+            }
+            status!(args.stream, "{}", summary.to_text().trim_end());
+        }
+        let Some(watch) = args.watch else {
+            break;
+        };
+        if engine.state().is_stopped() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(watch)).await;
+        if engine.state().is_stopped() {
+            break;
+        }
+        num_done = 0;
+    }
+
+    // Wait for the last results to be committed to the database
+    if let Some(db) = db {
+        if let Err(e) = db.finish() {
+            eprintln!(
+                "Error: failed to write to {}: {e}",
+                args.db.as_ref().unwrap().display()
+            );
+        }
+    }
+
+    // Wait for the stats_printer to complete before exiting. It never sees the scan complete if it
+    // was stopped early.
+    let stats_printer_handle = stats_printer_handle.unwrap();
+    if engine.state().is_stopped() {
+        stats_printer_handle.abort();
+    } else {
+        stats_printer_handle.await.unwrap();
+    }
 
-    // Spawn the stats printer task, which is given a reference to the shared state and the update
-    // interval from the cli arg.
+    drop(engine);
+    finish_pcap(pcap, &args);
+}
+
+/// Ping the targets on each line of stdin as they come in, for `--stdin`. Ping files are laid out
+/// by their range, so the results only go to stdout and the database.
+async fn scan_stdin(args: Args) {
+    if !args.stream && args.db.is_none() {
+        eprintln!("Error: --stdin needs --stream or --db for the results to go somewhere");
+        std::process::exit(1);
+    }
+    let pcap = open_pcap(&args);
+    let engine = engine_from_args(&args, None, 1, pcap.as_ref());
+    start_controls(&args, engine.state()).await;
+    let db = open_db(&args, "stdin".to_string(), 1);
+
+    let slowest = new_slowest(&args);
+
+    let (addr_tx, addr_rx) = mpsc::channel(STDIN_CHANNEL_CAPACITY);
+    tokio::spawn(read_targets(addr_tx, args.all_addresses));
     let stats_printer_handle = tokio::spawn(stats_printer(
-        state.clone(),
+        engine.state().clone(),
         Duration::from_secs(args.update_interval),
+        false,
+        vec![],
+        slowest.clone(),
+        args.stream,
     ));
+    let mut results = engine.scan_stream(ReceiverStream::new(addr_rx));
+    while let Some(result) = results.next().await {
+        if let Some(ref db) = db {
+            db.insert(result);
+        }
+        record_slowest(&slowest, result);
+        if args.stream {
+            print_result(result);
+        }
+    }
+    stats_printer_handle.abort();
 
-    // For every address in the iterator of remaining addresses:
-    for addr in addrs {
-        // Construct a pinger
-        let mut pinger = client.pinger(addr.into(), 0.into()).await;
-        // Set the timout based on the cli arg
-        pinger.timeout(Duration::from_secs(args.timeout));
-        // Add 1 to the running count
-        state.num_running.fetch_add(1, Ordering::Release);
-        // Spawn the worker
-        let handle = tokio::spawn(ping_worker(pinger, state.clone(), args.num_retries));
-        // Send the worker handle to the file writer
-        tx.send(handle).unwrap();
-        // Wait until the running count drops below the max threshold given in the cli arg
-        let num_running = state.num_running.load(Ordering::Acquire);
-        for _ in 0..num_running / args.speed_factor {
-            tokio::task::yield_now().await;
+    if let Some(db) = db {
+        if let Err(e) = db.finish() {
+            eprintln!(
+                "Error: failed to write to {}: {e}",
+                args.db.as_ref().unwrap().display()
+            );
         }
     }
+    let state = engine.state();
+    status!(
+        args.stream,
+        "{} addresses pinged, {} timeouts, {} errors",
+        state.num_done(),
+        state.num_timeouts(),
+        state.num_errors()
+    );
+
+    drop(engine);
+    finish_pcap(pcap, &args);
+}
 
-    // Now that all the pings have been sent, drop the channel sender in order to signal to the file writer
-    // that no more handles will be added to the message queue and it can exit once it is done.
-    drop(tx);
+/// Send the addresses of the targets on each line of stdin, in the same formats as `--subnets`.
+/// Lines that can't be parsed are skipped with a warning, so one bad line doesn't end the scan.
+async fn read_targets(tx: mpsc::Sender<Ipv4Addr>, all_addresses: bool) {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut line_number = 0;
+    while let Ok(Some(line)) = lines.next_line().await {
+        line_number += 1;
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (nets, explicit) = match parse_target(line) {
+            Ok(target) => target,
+            Err(e) => {
+                eprintln!("Warning: skipping line {line_number} of stdin: {e}");
+                continue;
+            }
+        };
+        let mut range = IpRange::new();
+        for net in nets {
+            range.add(net);
+        }
+        for addr in pinger::addresses(&range, all_addresses || explicit) {
+            if tx.send(addr).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Build the ping engine from the cli args, exiting if the settings are invalid or we don't have
+/// permission to send pings. Without a range, the addresses come from a stream.
+fn engine_from_args(
+    args: &Args,
+    range: Option<IpRange<Ipv4Net>>,
+    stride: u32,
+    pcap: Option<&PcapWriter>,
+) -> PingEngine {
+    let config = match range {
+        Some(range) => PingConfig::builder(range),
+        None => PingConfig::stream_builder(),
+    };
+    let config = config
+        .all_addresses(args.all_addresses)
+        .stride(stride as usize)
+        .speed_factor(args.speed_factor)
+        .concurrency(
+            args.concurrency
+                .or(args.auto_concurrency.then_some(INITIAL_AUTO_CONCURRENCY)),
+        )
+        .rate(args.rate)
+        .num_retries(args.num_retries)
+        .timeout(Duration::from_secs(args.timeout))
+        .adaptive_timeout(args.adaptive_timeout.map(Duration::from_secs))
+        .source(args.source)
+        .interface(args.interface.clone())
+        .tos(args.tos)
+        .capture(pcap.map(PcapWriter::sender));
+    let config = match args.identifier_base {
+        Some(base) => config.identifier_base(base),
+        None => config,
+    }
+    .build();
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+    match PingEngine::new(config) {
+        Ok(engine) => engine,
+        Err(e) => {
+            eprintln!("Failed to open an ICMP socket: {e}");
+            if e.kind() == std::io::ErrorKind::PermissionDenied {
+                eprintln!("{PERMISSION_HINT}");
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Start the tasks that let the scan be watched and controlled from outside
+async fn start_controls(args: &Args, state: &Arc<State>) {
+    // Serve the scan's progress for scraping. Nothing is measured for it until it's scraped.
+    if let Some(addr) = args.metrics_addr {
+        let listener = match TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("Error: failed to listen on {addr}: {e}");
+                std::process::exit(1);
+            }
+        };
+        tokio::spawn(metrics::serve(listener, state.clone()));
+    }
 
-    // Wait for the file writer and stats_printer to complete before exiting.
-    file_writer_handle.await.unwrap();
-    stats_printer_handle.await.unwrap();
+    // Find the concurrency that gets the rate that was asked for, and keep following it as the
+    // network changes.
+    if let Some(target_rate) = args.target_rate {
+        if !(target_rate > 0. && target_rate.is_finite()) {
+            eprintln!("Error: {target_rate} isn't a valid number of pings per second");
+            std::process::exit(1);
+        }
+        tokio::spawn(tune_concurrency(state.clone(), target_rate));
+    }
+
+    // Let the scan be paused to free up the network for a while, without losing any progress.
+    #[cfg(unix)]
+    tokio::spawn(pause_on_signals(state.clone(), args.stream));
+
+    // Stop sending pings once the time is up, which lets the pings in flight finish so the file is
+    // left in a state that can be resumed.
+    if let Some(max_duration) = args.max_duration {
+        let state = state.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(max_duration).await;
+            state.stop();
+        });
+    }
+}
+
+/// The slowest responders for `--show-slow`, if it was given
+fn new_slowest(args: &Args) -> Option<Arc<Mutex<Slowest>>> {
+    args.show_slow
+        .map(|n| Arc::new(Mutex::new(Slowest::new(n))))
+}
+
+fn record_slowest(slowest: &Option<Arc<Mutex<Slowest>>>, result: PingResult) {
+    if let (Some(slowest), Some(rtt)) = (slowest, result.rtt) {
+        slowest
+            .lock()
+            .unwrap()
+            .insert(result.addr, rtt.as_secs_f32());
+    }
+}
+
+/// Exit if the file was written with settings that it can't be resumed without
+fn check_resumable(file: &MappedFile, header: &Header, path: &Path) {
+    if file.header().run_length != header.run_length {
+        let with = if file.header().run_length {
+            "with"
+        } else {
+            "without"
+        };
+        eprintln!(
+            "Error: {} was written {with} --run-length, so it has to be resumed {with} it",
+            path.display()
+        );
+        std::process::exit(1);
+    }
+    if file.header().all_addresses != header.all_addresses {
+        let with = if file.header().all_addresses {
+            "with"
+        } else {
+            "without"
+        };
+        eprintln!(
+            "Error: {} was written {with} --all-addresses, so it has to be resumed {with} it",
+            path.display()
+        );
+        std::process::exit(1);
+    }
+    if file.header().stride != header.stride {
+        eprintln!(
+            "Error: {} was written with --stride {}, so it has to be resumed with it",
+            path.display(),
+            file.header().stride
+        );
+        std::process::exit(1);
+    }
+    if file.header().precision != header.precision {
+        eprintln!(
+            "Error: {} was written with --precision {:?}, so it has to be resumed with it",
+            path.display(),
+            file.header().precision
+        );
+        std::process::exit(1);
+    }
+}
+
+/// Create the capture from `--pcap`, if there is one. Exits if it can't be created.
+fn open_pcap(args: &Args) -> Option<PcapWriter> {
+    let pcap_path = args.pcap.as_ref()?;
+    // The local address is only known if the pings are sent from one
+    let local = args.source.unwrap_or(Ipv4Addr::UNSPECIFIED);
+    match PcapWriter::create(pcap_path, local, args.tos.unwrap_or(0)) {
+        Ok(pcap) => Some(pcap),
+        Err(e) => {
+            eprintln!("Error: failed to create {}: {e}", pcap_path.display());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Wait for the last packets to be written to the capture, which can only happen once the engine
+/// that sends them is gone
+fn finish_pcap(pcap: Option<PcapWriter>, args: &Args) {
+    if let Some(pcap) = pcap {
+        if let Err(e) = pcap.finish() {
+            eprintln!(
+                "Error: failed to write to {}: {e}",
+                args.pcap.as_ref().unwrap().display()
+            );
+        }
+    }
 }
 
+/// Open the database from `--db`, if there is one, with the settings of the scan of the given
+/// range. Exits if it can't be opened.
+fn open_db(args: &Args, range: String, stride: u32) -> Option<DbWriter> {
+    let db_path = args.db.as_ref()?;
+    let settings = [
+        ("version", env!("CARGO_PKG_VERSION").to_string()),
+        ("range", range),
+        ("all_addresses", args.all_addresses.to_string()),
+        ("stride", stride.to_string()),
+        ("timeout_secs", args.timeout.to_string()),
+        ("num_retries", args.num_retries.to_string()),
+        ("start_time", Metadata::now().to_string()),
+    ];
+    match DbWriter::open(db_path, &settings) {
+        Ok(db) => Some(db),
+        Err(e) => {
+            eprintln!("Error: failed to open {}: {e}", db_path.display());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Write the results of a scan that skipped the first `num_done` addresses to the file at the
+/// given path, and to stdout as well if they're being streamed
+async fn scan(
+    path: &Path,
+    header: &Header,
+    legacy: bool,
+    num_done: u64,
+    results: impl Stream<Item = PingResult> + Unpin,
+    flush_interval: Option<Duration>,
+    stream: bool,
+) {
+    // Open (or create) the file and write the header, which is the same for every run over this
+    // range.
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .await
+        .unwrap();
+    let data_start = if legacy {
+        0
+    } else {
+        file.write_all(&header.to_bytes()).await.unwrap();
+        header.size()
+    };
+
+    // Seek forward in the file to the entry after the last complete entry.  If the file was just
+    // created then this entry will just be the start of the file.
+    let result_size = header.precision.size();
+    let mut data_len = num_done * result_size;
+    if header.run_length {
+        // Entries don't line up with addresses in a run-length encoded file, so the last complete
+        // entry is found from the length of the file instead. Anything after it is cut off,
+        // including what's left of a longer pass in watch mode.
+        if num_done > 0 {
+            let file_len = file.metadata().await.unwrap().len();
+            data_len = (file_len - data_start) / result_size * result_size;
+        }
+        file.set_len(data_start + data_len).await.unwrap();
+    }
+    file.seek(SeekFrom::Start(data_start + data_len))
+        .await
+        .unwrap();
+
+    // Write the results as they come in, wrapping the file in a BufWriter to speed up writes.
+    let buf_writer = BufWriter::with_capacity(4 * 100, file);
+    let writer = ResultWriter::new(buf_writer, header);
+    file_writer(results, writer, flush_interval, stream).await;
+}
+
+/// Like [`scan`], but for a compressed file. Compressed files can't be written to in place, so the
+/// results already in the file are read into memory and written back out before the new ones.
+async fn scan_compressed(
+    path: &Path,
+    header: &Header,
+    num_done: u64,
+    results: impl Stream<Item = PingResult> + Unpin,
+    flush_interval: Option<Duration>,
+    stream: bool,
+) {
+    let old = match num_done {
+        0 => None,
+        _ => Some(MappedFile::open(path).await.unwrap()),
+    };
+    let file = File::create(path).await.unwrap();
+    let mut encoder = GzipEncoder::new(BufWriter::new(file));
+    encoder.write_all(&header.to_bytes()).await.unwrap();
+    let mut writer = ResultWriter::new(encoder, header);
+    if let Some(old) = old {
+        for offset in 0..num_done {
+            writer.write(old.get(offset).unwrap()).await.unwrap();
+        }
+    }
+    file_writer(results, writer, flush_interval, stream).await;
+}
+
+/// Write the results to the file as they come in. With a flush interval, what's been buffered is
+/// also written through that often, so a crash loses at most that much of the scan.
 async fn file_writer(
-    mut rx: UnboundedReceiver<JoinHandle<Option<Duration>>>,
-    mut file: BufWriter<File>,
+    mut results: impl Stream<Item = PingResult> + Unpin,
+    mut file: ResultWriter<impl AsyncWrite + Unpin>,
+    flush_interval: Option<Duration>,
+    stream: bool,
 ) {
-    // As long as there is another ping worker handle in the message queue:
-    // Wait for the ping to either return or timeout.
-    while let Some(handle) = rx.recv().await {
+    let mut flush_timer = flush_interval.map(|interval| {
+        let mut timer = tokio::time::interval(interval);
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        timer
+    });
+    // As long as the engine has another result, which comes out once the ping either returns or
+    // times out:
+    loop {
+        let result = tokio::select! {
+            result = results.next() => result,
+            _ = async { flush_timer.as_mut().unwrap().tick().await }, if flush_timer.is_some() => {
+                file.flush().await.unwrap();
+                continue;
+            }
+        };
+        let Some(result) = result else {
+            break;
+        };
         // Get the duration of the ping, or use -1 for a timeout.
-        let num = match handle.await.unwrap() {
+        let num = match result.rtt {
             Some(dur) => dur.as_secs_f32(),
             None => -1.,
         };
         // Write the number to the file in binary.
-        file.write_f32(num).await.unwrap();
+        file.write(num)
+            .instrument(tracing::trace_span!("Write Result"))
+            .await
+            .unwrap();
+        if stream {
+            print_result(result);
+        }
+    }
+    // Once completed, flush the buffer to the file. This also finishes the stream of a compressed
+    // file.
+    file.shutdown().await.unwrap();
+}
+
+/// Print a result as a line of JSON for `--stream`, in milliseconds with null for a timeout.
+/// Stdout is line buffered, so each result comes out as soon as it's in.
+fn print_result(result: PingResult) {
+    let rtt_ms = result.rtt.map(|rtt| rtt.as_secs_f64() * 1e3);
+    println!(
+        "{}",
+        serde_json::json!({"ip": result.addr, "rtt_ms": rtt_ms})
+    );
+}
+
+/// Write the metadata for the ping file at the given path. Failing to is only worth a warning,
+/// since the results are what matter.
+async fn save_metadata(metadata: &Metadata, path: &Path) {
+    if let Err(e) = metadata.save(path).await {
+        eprintln!(
+            "Warning: failed to write {}: {e}",
+            Metadata::path(path).display()
+        );
+    }
+}
+
+/// Keep scaling the concurrency by how far the ping rate is from the target. It's only raised while
+/// it's what's holding the rate back, so it doesn't run away when something else is.
+async fn tune_concurrency(state: Arc<State>, target_rate: f64) {
+    let mut last_time = Instant::now();
+    let mut last_value = state.num_done();
+    loop {
+        tokio::time::sleep(AUTO_CONCURRENCY_INTERVAL).await;
+        // Measured the same way as in `stats_printer`
+        let now = Instant::now();
+        let done = state.num_done();
+        let rate = done.saturating_sub(last_value) as f64 / (now - last_time).as_secs_f64();
+        last_time = now;
+        last_value = done;
+        let Some(concurrency) = state.concurrency() else {
+            return;
+        };
+        if state.is_paused() {
+            continue;
+        }
+        let limited = state.num_running() * 10 >= concurrency * 9;
+        if rate < target_rate && !limited {
+            continue;
+        }
+        let ratio = if rate > 0. {
+            (target_rate / rate).clamp(0.5, 2.)
+        } else {
+            2.
+        };
+        let new_concurrency = (concurrency as f64 * ratio.powf(AUTO_CONCURRENCY_GAIN)).round();
+        state.set_concurrency((new_concurrency as usize).min(MAX_AUTO_CONCURRENCY));
+    }
+}
+
+/// Toggle whether the scan is paused on SIGUSR1, and resume it on SIGUSR2
+#[cfg(unix)]
+async fn pause_on_signals(state: Arc<State>, stream: bool) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut toggle = signal(SignalKind::user_defined1()).unwrap();
+    let mut resume = signal(SignalKind::user_defined2()).unwrap();
+    loop {
+        tokio::select! {
+            _ = toggle.recv() => {
+                if state.is_paused() {
+                    state.resume();
+                } else {
+                    state.pause();
+                }
+            }
+            _ = resume.recv() => state.resume(),
+        }
+        if state.is_paused() {
+            status!(stream, "Paused, send SIGUSR1 or SIGUSR2 to resume");
+        } else {
+            status!(stream, "Resumed");
+        }
     }
-    // Once completed, flush the buffer to the file.
-    file.flush().await.unwrap();
 }
 
-async fn stats_printer(state: Arc<State>, interval: Duration) {
+async fn stats_printer(
+    state: Arc<State>,
+    interval: Duration,
+    exit_when_done: bool,
+    subnets: Vec<(Ipv4Net, u64)>,
+    slowest: Option<Arc<Mutex<Slowest>>>,
+    stream: bool,
+) {
     // Set the baseline time and progress value
     let mut last_time = Instant::now();
-    let mut last_value = state.num_done.load(Ordering::Acquire);
+    let mut last_value = state.num_done();
     loop {
         // Wait for the interval specified in the cli args
         tokio::time::sleep(interval).await;
         // Get the current time, progress value, and running count
         let now = Instant::now();
-        let done = state.num_done.load(Ordering::Acquire);
-        let active = state.num_running.load(Ordering::Acquire);
-        // Calculate the progress percentage and the ping rate
-        let perc_done = done as f64 / state.total as f64 * 100.;
-        let rate = (done - last_value) as f64 / (now - last_time).as_secs_f64();
+        let done = state.num_done();
+        let active = state.num_running();
+        let timeouts = state.num_timeouts();
+        let errors = state.num_errors();
+        // Calculate the progress percentage and the ping rate. The progress goes back to 0 at the
+        // start of every pass in watch mode. Without a total, the number done is shown instead.
+        let progress = match state.total() {
+            Some(total) => format!("{:>7.3}% done", done as f64 / total as f64 * 100.),
+            None => format!("{done:>9} done"),
+        };
+        let rate = done.saturating_sub(last_value) as f64 / (now - last_time).as_secs_f64();
         // Set the baselines to the current values
         last_time = now;
         last_value = done;
+        let paused = if state.is_paused() { " | paused" } else { "" };
+        let limit = match state.concurrency() {
+            Some(concurrency) => format!(" / {concurrency:<6}"),
+            None => String::new(),
+        };
         // Print the results
-        println!("{perc_done:>7.3}% done | {rate:>9.2} p/s | {active:>6} active",);
+        status!(
+            stream,
+            "{progress} | {rate:>9.2} p/s | {active:>6}{limit} active | \
+             {timeouts:>9} timeouts | {errors:>6} errors{paused}",
+        );
+        // Addresses are pinged in order, so each subnet's progress is how far the number done has
+        // got between its first and last offset
+        let mut start = 0;
+        for &(net, end) in &subnets {
+            // A big stride can skip over a small subnet entirely
+            if end == start {
+                continue;
+            }
+            let perc_done = (done.clamp(start, end) - start) as f64 / (end - start) as f64 * 100.;
+            status!(stream, "    {net:<18} {perc_done:>7.3}% done");
+            start = end;
+        }
+        if let Some(ref slowest) = slowest {
+            let slowest = slowest.lock().unwrap().to_vec();
+            if !slowest.is_empty() {
+                status!(stream, "    slowest so far:");
+            }
+            for (addr, rtt) in slowest {
+                status!(stream, "      {addr:<15} {:.2}ms", rtt * 1e3);
+            }
+        }
         // If the number completed is equal to the total number of addrs, then we must
         // be done, so we exit the loop
-        if done == state.total {
+        if exit_when_done && Some(done) == state.total() {
             break;
         }
     }
 }
 
-async fn ping_worker(mut pinger: surge_ping::Pinger, state: Arc<State>, num_retries: usize) -> Option<Duration> {
-    // Start the ping and await its return.
-    let mut i = 1;
-    let reply = loop {
-        let reply = pinger.ping(0.into(), &[]).await;
-        if i >= num_retries || reply.is_ok() {
-            break reply;
-        }
-        i += 1;
-    };
-    // Now that the ping has returned, add 1 to num_done and subtract 1 from the running count
-    state.num_done.fetch_add(1, Ordering::Release);
-    state.num_running.fetch_sub(1, Ordering::Release);
-    // Return an optional duration based on if the ping timed out or returned successfully.
-    reply.ok().map(|(_, dur)| dur)
+/// Each subnet in the range along with the offset just past its last result, in the order the
+/// subnets are scanned
+fn subnet_ends(header: &Header) -> Vec<(Ipv4Net, u64)> {
+    let stride = header.stride as u64;
+    let mut num_addrs = 0;
+    header
+        .range
+        .iter()
+        .map(|net| {
+            num_addrs += if header.all_addresses {
+                1 << (32 - net.prefix_len())
+            } else {
+                net.hosts().count() as u64
+            };
+            // Only every `stride`th address is pinged, starting from the first one in the range
+            (net, num_addrs.div_ceil(stride))
+        })
+        .collect()
 }
 
-fn path_from_range(mut range: IpRange<Ipv4Net>) -> Result<PathBuf, std::fmt::Error> {
+/// Build the range of addresses to scan from the subnets and the subnets file, minus any
+/// exclusions. The default subnets are only used if neither is given. Also returns whether the
+/// targets were all single addresses or `start-end` ranges, which need every address in the range
+/// pinged, since they're stored as the subnets that cover them. They can't be mixed with subnets
+/// unless every address of those is pinged too.
+async fn range_from_args(args: &Args) -> Result<(IpRange<Ipv4Net>, bool), String> {
+    let mut targets = vec![];
+    if let Some(ref path) = args.subnets_file {
+        targets = read_subnets_file(path).await?;
+    }
+    let subnets = if args.subnets.is_empty() && args.subnets_file.is_none() {
+        DEFAULT_SUBNETS.to_vec()
+    } else {
+        args.subnets.iter().map(String::as_str).collect()
+    };
+    for target in subnets {
+        targets.push(parse_target(target)?);
+    }
+    let num_explicit = targets.iter().filter(|(_, explicit)| *explicit).count();
+    if num_explicit > 0 && num_explicit < targets.len() && !args.all_addresses {
+        return Err(
+            "single addresses and start-end ranges can only be mixed with subnets with \
+                    --all-addresses, since the network and broadcast addresses of the subnets \
+                    would be left out of them otherwise"
+                .to_string(),
+        );
+    }
+    let mut range = IpRange::new();
+    for net in targets.into_iter().flat_map(|(nets, _)| nets) {
+        range.add(net);
+    }
+    // The output filename is derived from the resulting range, so resuming with the same exclusions
+    // finds the same file.
+    let mut excluded = IpRange::new();
+    for target in &args.exclude {
+        for net in parse_target(target)?.0 {
+            excluded.add(net);
+        }
+    }
+    let mut range = range.exclude(&excluded);
     range.simplify();
-    let mut name = String::new();
-    for net in range.iter() {
-        write!(name, "{}-{}_", net.network(), net.prefix_len())?;
+    Ok((range, num_explicit > 0))
+}
+
+/// Make sure there's at least one address in the range to scan, with the exclusions already taken
+/// out of it
+fn check_not_empty(range: &IpRange<Ipv4Net>, args: &Args) -> Result<(), String> {
+    if pinger::addresses(range, args.all_addresses)
+        .next()
+        .is_some()
+    {
+        return Ok(());
+    }
+    if args.exclude.is_empty() {
+        Err("No addresses to scan".to_string())
+    } else {
+        Err("No addresses to scan, every address has been excluded".to_string())
     }
-    name.pop();
-    write!(name, ".ping")?;
-    Ok(name.into())
 }
 
-struct State {
-    num_done: AtomicU64,
-    num_running: AtomicUsize,
-    total: u64,
+/// Read the targets listed one per line in the given file, skipping blank lines and `#` comments
+async fn read_subnets_file(path: &Path) -> Result<Vec<(Vec<Ipv4Net>, bool)>, String> {
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut targets = vec![];
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let target =
+            parse_target(line).map_err(|e| format!("{}:{}: {e}", path.display(), i + 1))?;
+        targets.push(target);
+    }
+    Ok(targets)
+}
+
+/// Parse a duration like `90s`, `30m`, `12h` or `2d`, where a number on its own is in seconds
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let secs_per_unit = match unit {
+        "s" => 1.,
+        "m" => 60.,
+        "h" => 60. * 60.,
+        "d" => 24. * 60. * 60.,
+        _ => return Err(format!("unknown unit {unit:?}, expected s, m, h or d")),
+    };
+    let number = number
+        .parse::<f64>()
+        .map_err(|e| format!("invalid duration {s:?}: {e}"))?;
+    Duration::try_from_secs_f64(number * secs_per_unit)
+        .map_err(|e| format!("invalid duration {s:?}: {e}"))
 }
-impl State {
-    fn new(total: u64, done: u64) -> Self {
-        Self {
-            num_done: AtomicU64::new(done),
-            num_running: AtomicUsize::new(0),
-            total,
+
+/// Parse a subnet in CIDR notation, a single address, or an inclusive `start-end` range of
+/// addresses into the subnets that exactly cover it. Also returns whether the addresses were named
+/// explicitly, as a single address or a range, in which case all of them have to be pinged.
+fn parse_target(target: &str) -> Result<(Vec<Ipv4Net>, bool), String> {
+    if let Some((start, end)) = target.split_once('-') {
+        let parse_addr = |s: &str| {
+            s.trim()
+                .parse::<Ipv4Addr>()
+                .map_err(|e| format!("invalid address {s:?} in range {target:?}: {e}"))
+        };
+        let (start, end) = (parse_addr(start)?, parse_addr(end)?);
+        if start > end {
+            return Err(format!("range {target:?} ends before it starts"));
         }
+        return Ok((Ipv4Subnets::new(start, end, 0).collect(), true));
     }
+    if let Ok(addr) = target.parse::<Ipv4Addr>() {
+        return Ok((vec![addr.into()], true));
+    }
+    let net = target
+        .parse::<Ipv4Net>()
+        .map_err(|e| format!("invalid subnet {target:?}: {e}"))?;
+    Ok((vec![net], false))
+}
+
+/// Add a timestamp to the filename of the given path, so that each pass of a scan gets its own file
+fn rotated_path(path: &Path) -> PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    // the stem of a compressed file still has the .ping extension
+    let (stem, extension) = if ping_file::is_compressed(path) {
+        let stem = Path::new(path.file_stem().unwrap()).file_stem().unwrap();
+        (stem, "ping.gz")
+    } else {
+        (path.file_stem().unwrap(), "ping")
+    };
+    let stem = stem.to_string_lossy();
+    path.with_file_name(format!("{stem}.{timestamp}.{extension}"))
 }
 
 #[derive(Debug, clap::Args)]
 pub struct Args {
+    /// Only ping every Nth address, for a quick sample of a big range
+    #[arg(default_value_t = 1, long, value_parser = clap::value_parser!(u32).range(1..))]
+    stride: u32,
     /// A unitless nmumber representing how fast to send pings (higher is faster)
     #[arg(default_value_t = 2, short = 'f', long)]
     speed_factor: usize,
+    /// The most pings to have in flight at once, or where --auto-concurrency starts from
+    #[arg(long)]
+    concurrency: Option<usize>,
+    /// Keep adjusting the most pings to have in flight to hold the ping rate near --target-rate,
+    /// starting from --concurrency or 1000
+    #[arg(long, requires = "target_rate", conflicts_with = "rate")]
+    auto_concurrency: bool,
+    /// The pings per second for --auto-concurrency to aim for
+    #[arg(long, requires = "auto_concurrency")]
+    target_rate: Option<f64>,
+    /// The most pings to send per second
+    #[arg(long)]
+    rate: Option<f64>,
     /// The number of times to retry pinging each address
     #[arg(default_value_t = 5, short, long)]
     num_retries: usize,
+    /// Also print the progress of each subnet in the range along with the stats
+    #[arg(long)]
+    per_subnet: bool,
+    /// Write the buffered results through to the file this often in seconds, so a crash loses at
+    /// most this much of the scan. Otherwise they're only written when the buffer fills up
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    flush_interval: Option<u64>,
     /// The interval for printings stats in seconds
     #[arg(default_value_t = 1, short, long)]
     update_interval: u64,
     /// The ping timeout in seconds
     #[arg(default_value_t = 2, short, long)]
     timeout: u64,
-    /// The subnets to ping
-    #[arg(default_values_t = vec!["142.244.0.0/16".to_string(), "129.128.0.0/16".to_string()], short, long)]
+    /// Start with --timeout, then time out after a few times the 95th percentile of the recent
+    /// ping times instead, but never after more than this many seconds
+    #[arg(long, value_name = "MAX_SECS")]
+    adaptive_timeout: Option<u64>,
+    /// The subnets to ping, as CIDR subnets, single addresses, or `start-end` address ranges,
+    /// separated by commas or given more than once (defaults to 142.244.0.0/16 and 129.128.0.0/16)
+    #[arg(short, long, env = "PINGER_SUBNETS", value_delimiter = ',')]
     subnets: Vec<String>,
+    /// A file listing subnets to ping, one per line in the same formats as `--subnets`
+    #[arg(long)]
+    subnets_file: Option<PathBuf>,
+    /// Subnets to leave out of the scan
+    #[arg(short, long)]
+    exclude: Vec<String>,
+    /// The file to write results to (defaults to a name derived from the subnets, in the current
+    /// directory). Results are compressed if its name ends in .gz
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+    /// The directory to put the file with the default name in, when there's no --out
+    #[arg(default_value = ".", long, env = "PINGER_OUT_DIR")]
+    out_dir: PathBuf,
+    /// Store each run of timeouts as a single result, which makes scans of sparse ranges much
+    /// smaller. Tools that need to look up results by address have to expand these files in memory
+    #[arg(long)]
+    run_length: bool,
+    /// How precisely to store each ping time
+    #[arg(default_value = "f32", long)]
+    precision: Precision,
+    /// Compress the results with gzip, adding .gz to the default filename. Compressed files can only
+    /// be viewed up to where the compressor has got to, so it's best to wait for the scan to finish
+    #[arg(long, conflicts_with = "out")]
+    compress: bool,
+    /// Scan the range again this many seconds after each pass finishes, overwriting the results
+    #[arg(short, long)]
+    watch: Option<u64>,
+    /// Write each pass to a new file with a timestamp in its name instead of overwriting the results
+    #[arg(long, requires = "watch")]
+    rotate: bool,
+    /// The local address to send pings from
+    #[arg(long)]
+    source: Option<Ipv4Addr>,
+    /// The network interface to send pings from (Linux only)
+    #[arg(long)]
+    interface: Option<String>,
+    /// Give each address the ICMP identifier N plus its last 16 bits, instead of offsetting them
+    /// from the process ID, for networks that filter pings by identifier. Distinct identifiers
+    /// keep replies from being mixed up at high concurrency
+    #[arg(long, value_name = "N")]
+    identifier_base: Option<u16>,
+    /// The type of service byte to mark each ping with, from 0 to 255, for testing how networks
+    /// treat differentiated services. The DSCP is the upper 6 bits, so DSCP 46 (EF) is 184 (Unix
+    /// only)
+    #[arg(long)]
+    tos: Option<u8>,
+    /// Also ping the network and broadcast address of each subnet
+    #[arg(long)]
+    all_addresses: bool,
+    /// The number of slowest responders to list in the summary at the end of the scan
+    #[arg(default_value_t = 10, long)]
+    num_slowest: usize,
+    /// Also list the N slowest responders so far along with the stats at every update
+    #[arg(long, value_name = "N")]
+    show_slow: Option<usize>,
+    /// Print the summary at the end of the scan as JSON
+    #[arg(long)]
+    json: bool,
+    /// Write when the scan ran and with what settings to a JSON file next to the results, ending
+    /// in .meta.json
+    #[arg(long)]
+    metadata: bool,
+    /// Stop sending pings after this long, like `30m` or `8h`, even if the scan isn't done. The
+    /// scan can be resumed later
+    #[arg(long, value_parser = parse_duration)]
+    max_duration: Option<Duration>,
+    /// Print how many addresses would be pinged and where the results would go, then exit
+    #[arg(long)]
+    dry_run: bool,
+    /// Start the file over if it's a scan of a different range, instead of refusing to resume it.
+    /// The old file is moved aside with a timestamp added to its name, like with --rotate.
+    #[arg(long)]
+    force: bool,
+    /// Ping the addresses and subnets on each line of stdin as they come in, in the same formats as
+    /// `--subnets`, instead of a range. The results can't go in a ping file, so this needs
+    /// `--stream` or `--db`
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "subnets", "subnets_file", "exclude", "out", "compress", "watch", "run_length",
+            "stride", "per_subnet", "metadata", "json", "dry_run",
+        ],
+    )]
+    stdin: bool,
+    /// Also print each result to stdout as a line of JSON as soon as it's in, like
+    /// {"ip":"10.0.0.1","rtt_ms":1.5}, with an rtt_ms of null for a timeout. Everything else that
+    /// would be printed to stdout goes to stderr instead
+    #[arg(long)]
+    stream: bool,
+    /// Also insert each result into a table in this SQLite database, as `ip TEXT, rtt_ms REAL, ts
+    /// INTEGER` where timeouts have a NULL rtt_ms, with the scan's settings in a metadata table
+    #[arg(long)]
+    db: Option<PathBuf>,
+    /// Serve the scan's progress as Prometheus metrics over HTTP on this address, like
+    /// 127.0.0.1:9100
+    #[arg(long, value_name = "HOST:PORT")]
+    metrics_addr: Option<SocketAddr>,
+    /// Write the ICMP echo requests and replies of the scan to this pcap file, for looking at in
+    /// Wireshark. The packets are rebuilt from the pings rather than captured off the wire, so
+    /// they're addressed from --source, or 0.0.0.0 without it, and unmatched replies are missing
+    #[arg(long, value_name = "FILE")]
+    pcap: Option<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        args: Args,
+    }
+
+    fn parse_args(args: &[&str]) -> Args {
+        Cli::parse_from(std::iter::once("ping").chain(args.iter().copied())).args
+    }
+
+    /// Every address that the targets name, in ascending order
+    fn expand(targets: &[&str]) -> Vec<Ipv4Addr> {
+        let mut range = IpRange::new();
+        let mut explicit = false;
+        for target in targets {
+            let (nets, target_explicit) = parse_target(target).unwrap();
+            explicit |= target_explicit;
+            for net in nets {
+                range.add(net);
+            }
+        }
+        range.simplify();
+        let mut addrs = pinger::addresses(&range, explicit).collect::<Vec<_>>();
+        // the range iterates its nets in trie order rather than by address
+        addrs.sort();
+        addrs
+    }
+
+    #[test]
+    fn start_end_range_is_inclusive() {
+        for (start, end) in [
+            ("10.0.0.4", "10.0.0.7"),
+            ("10.0.0.3", "10.0.1.8"),
+            ("10.0.0.0", "10.0.0.255"),
+            ("10.0.0.9", "10.0.0.9"),
+        ] {
+            let expected = ipnet::Ipv4AddrRange::new(start.parse().unwrap(), end.parse().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(expand(&[&format!("{start}-{end}")]), expected);
+        }
+    }
+
+    #[test]
+    fn single_addresses_are_all_pinged() {
+        let targets = ["10.0.0.0", "10.0.0.1", "10.0.0.2", "10.0.0.3"];
+        let expected = targets.map(|t| t.parse::<Ipv4Addr>().unwrap());
+        assert_eq!(expand(&targets), expected);
+    }
+
+    #[test]
+    fn subnets_leave_out_network_and_broadcast() {
+        let addrs = expand(&["10.0.0.0/30"]);
+        assert_eq!(
+            addrs,
+            [Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)]
+        );
+    }
+
+    #[test]
+    fn empty_range_has_nothing_to_scan() {
+        let args = parse_args(&[]);
+        assert_eq!(
+            check_not_empty(&IpRange::new(), &args),
+            Err("No addresses to scan".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn excluding_everything_leaves_nothing_to_scan() {
+        let args = parse_args(&[
+            "--subnets",
+            "10.0.0.0/24,10.0.1.0/24",
+            "--exclude",
+            "10.0.0.0/23",
+        ]);
+        let (range, _) = range_from_args(&args).await.unwrap();
+        assert_eq!(
+            check_not_empty(&range, &args),
+            Err("No addresses to scan, every address has been excluded".to_string())
+        );
+        let args = parse_args(&["--subnets", "10.0.0.0/24", "--exclude", "10.0.0.0/25"]);
+        let (range, _) = range_from_args(&args).await.unwrap();
+        assert_eq!(check_not_empty(&range, &args), Ok(()));
+    }
 }
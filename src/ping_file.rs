@@ -0,0 +1,640 @@
+use async_compression::tokio::bufread::GzipDecoder;
+use half::f16;
+use ipnet::Ipv4Net;
+use iprange::IpRange;
+use memmap2::Mmap;
+use std::{
+    fmt::Write,
+    io::{self, Cursor, SeekFrom},
+    net::Ipv4Addr,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs::File,
+    io::{
+        AsyncBufRead, AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt,
+        BufReader,
+    },
+};
+
+/// The size of each result in a file with full precision results, which includes every file written
+/// before headers existed
+pub const DATA_SIZE: u64 = std::mem::size_of::<f32>() as u64;
+
+/// The bytes that every file with a header starts with. Read as the first result of a file
+/// without a header, this would be a ping time of hundreds of years, so the two can't be confused.
+const MAGIC: [u8; 4] = *b"PNGR";
+const VERSION: u16 = 2;
+
+const FLAG_ALL_ADDRESSES: u16 = 1 << 0;
+const FLAG_RUN_LENGTH: u16 = 1 << 1;
+const FLAG_HALF_PRECISION: u16 = 1 << 2;
+/// Set if only every few addresses were pinged, in which case the stride follows the flags
+const FLAG_STRIDE: u16 = 1 << 3;
+
+/// Ranges with more subnets than this get a filename made from a hash of the range instead of
+/// listing every subnet, which could go past the filesystem's limit on the length of a filename
+const MAX_NAMED_SUBNETS: usize = 8;
+
+/// The header at the start of a ping file, which describes which address each result belongs to.
+/// Files written before headers were introduced don't have one, in which case it is derived from
+/// the filename.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub range: IpRange<Ipv4Net>,
+    /// Whether the network and broadcast addresses of each subnet were pinged
+    pub all_addresses: bool,
+    /// Whether runs of timeouts are stored as a single result, see [`ResultWriter`]
+    pub run_length: bool,
+    /// How each result is stored, which decides the stride between results
+    pub precision: Precision,
+    /// Only every `stride`th address of the range was pinged
+    pub stride: u32,
+}
+impl Header {
+    /// Build the header for a file written before headers existed
+    pub fn from_path(path: impl AsRef<Path>) -> io::Result<Self> {
+        let range = parse_range_from_path(path).ok_or_else(|| {
+            invalid_data("the file has no header and its name doesn't say which range it has")
+        })?;
+        Ok(Self {
+            range,
+            all_addresses: false,
+            run_length: false,
+            precision: Precision::F32,
+            stride: 1,
+        })
+    }
+    /// Iterate over the addresses in the order their results appear in the file
+    pub fn addresses(&self) -> impl Iterator<Item = Ipv4Addr> + '_ {
+        self.addresses_with_offsets().map(|(_, addr)| addr)
+    }
+    /// Iterate over the addresses along with the offsets of their results in the file, like
+    /// [`pinger::addresses_with_offsets`] but only with the addresses that a stride leaves in
+    pub fn addresses_with_offsets(&self) -> impl Iterator<Item = (u64, Ipv4Addr)> + '_ {
+        let addrs = pinger::addresses(&self.range, self.all_addresses);
+        (0..).zip(addrs.step_by(self.stride as usize))
+    }
+    /// The number of bytes the header takes up at the start of the file
+    pub fn size(&self) -> u64 {
+        self.to_bytes().len() as u64
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = 0;
+        if self.all_addresses {
+            flags |= FLAG_ALL_ADDRESSES;
+        }
+        if self.run_length {
+            flags |= FLAG_RUN_LENGTH;
+        }
+        if self.precision == Precision::F16 {
+            flags |= FLAG_HALF_PRECISION;
+        }
+        if self.stride != 1 {
+            flags |= FLAG_STRIDE;
+        }
+        // Files that older versions can read are marked as version 1, so they still can
+        let version = if flags & !FLAG_ALL_ADDRESSES != 0 {
+            VERSION
+        } else {
+            1
+        };
+        let nets = self.range.iter().collect::<Vec<_>>();
+        let mut bytes = vec![];
+        bytes.extend(MAGIC);
+        bytes.extend(version.to_be_bytes());
+        bytes.extend(flags.to_be_bytes());
+        if self.stride != 1 {
+            bytes.extend(self.stride.to_be_bytes());
+        }
+        bytes.extend((nets.len() as u32).to_be_bytes());
+        for net in nets {
+            bytes.extend(net.network().octets());
+            bytes.push(net.prefix_len());
+        }
+        bytes
+    }
+    /// Read the header from the start of a file. Returns `None` (and rewinds the reader) if the
+    /// file doesn't have a header. A file that stops partway through the magic has its header
+    /// still being written, which is an `UnexpectedEof` error like a file that stops partway
+    /// through the rest of the header.
+    pub async fn read(
+        reader: &mut (impl AsyncRead + AsyncSeek + Unpin),
+    ) -> io::Result<Option<Self>> {
+        let mut magic = [0; 4];
+        let mut len = 0;
+        while len < magic.len() {
+            match reader.read(&mut magic[len..]).await? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        if magic[..len] != MAGIC[..len] {
+            reader.seek(SeekFrom::Start(0)).await?;
+            return Ok(None);
+        }
+        if len < magic.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the header hasn't been written yet",
+            ));
+        }
+        Self::read_after_magic(reader).await.map(Some)
+    }
+    /// Read the header from the start of a compressed file, which always has one since compression
+    /// was added after headers
+    async fn read_compressed(reader: &mut (impl AsyncRead + Unpin)) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic).await?;
+        if magic != MAGIC {
+            return Err(invalid_data("the compressed file doesn't have a header"));
+        }
+        Self::read_after_magic(reader).await
+    }
+    async fn read_after_magic(reader: &mut (impl AsyncRead + Unpin)) -> io::Result<Self> {
+        let version = reader.read_u16().await?;
+        if version > VERSION {
+            return Err(invalid_data(format!("unsupported file version {version}")));
+        }
+        let flags = reader.read_u16().await?;
+        let stride = match flags & FLAG_STRIDE {
+            0 => 1,
+            _ => reader.read_u32().await?,
+        };
+        if stride == 0 {
+            return Err(invalid_data("the stride is 0"));
+        }
+        let num_nets = reader.read_u32().await?;
+        let mut range = IpRange::new();
+        for _ in 0..num_nets {
+            let network = Ipv4Addr::from(reader.read_u32().await?);
+            let prefix_len = reader.read_u8().await?;
+            let net = Ipv4Net::new(network, prefix_len).map_err(invalid_data)?;
+            range.add(net);
+        }
+        Ok(Self {
+            range,
+            all_addresses: flags & FLAG_ALL_ADDRESSES != 0,
+            run_length: flags & FLAG_RUN_LENGTH != 0,
+            precision: if flags & FLAG_HALF_PRECISION != 0 {
+                Precision::F16
+            } else {
+                Precision::F32
+            },
+            stride,
+        })
+    }
+    /// Check whether the file at the given path was written before headers existed. A file whose
+    /// header was cut off has one, even if it can't be read.
+    pub async fn is_missing(path: impl AsRef<Path>) -> io::Result<bool> {
+        let mut buf_reader = BufReader::new(File::open(path).await?);
+        match Self::read(&mut buf_reader).await {
+            Ok(header) => Ok(header.is_none()),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Whether the file at the given path is gzip compressed, which is decided by its extension
+pub fn is_compressed(path: impl AsRef<Path>) -> bool {
+    path.as_ref().extension().is_some_and(|e| e == "gz")
+}
+
+/// A reader over the results in a ping file, which decompresses them if the file is compressed
+pub type Reader = Box<dyn AsyncBufRead + Send + Unpin>;
+
+/// Open a ping file for reading, returning its header and a reader positioned at the first result
+pub async fn open(path: impl AsRef<Path>) -> io::Result<(Header, Reader)> {
+    let file = File::open(&path).await?;
+    let mut buf_reader = BufReader::new(file);
+    if is_compressed(&path) {
+        let mut decoder = BufReader::new(GzipDecoder::new(buf_reader));
+        let header = Header::read_compressed(&mut decoder).await?;
+        return Ok((header, Box::new(decoder)));
+    }
+    let header = match Header::read(&mut buf_reader).await? {
+        Some(header) => header,
+        None => Header::from_path(path)?,
+    };
+    Ok((header, Box::new(buf_reader)))
+}
+
+/// Random access to the results in a ping file, for files that are no longer being written to. The
+/// file is memory mapped rather than read into memory, so this is cheap even for huge files.
+/// Compressed and run-length encoded files have to be expanded into memory instead.
+pub struct MappedFile {
+    header: Header,
+    data_start: usize,
+    contents: Contents,
+}
+impl MappedFile {
+    pub async fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let contents = if is_compressed(&path) {
+            let file = BufReader::new(File::open(&path).await?);
+            let mut bytes = vec![];
+            // A compressed file that's cut off still has results up to where it stops
+            match GzipDecoder::new(file).read_to_end(&mut bytes).await {
+                Err(e) if e.kind() != io::ErrorKind::UnexpectedEof => return Err(e),
+                _ => Contents::Decompressed(bytes),
+            }
+        } else {
+            let file = std::fs::File::open(&path)?;
+            // SAFETY: Ping files are only ever appended to or overwritten in place with the same
+            // layout, so at worst another process changes the value of a result while we read it.
+            Contents::Mapped(unsafe { Mmap::map(&file)? })
+        };
+        let mut cursor = Cursor::new(&contents[..]);
+        let header = match Header::read(&mut cursor).await? {
+            Some(header) => header,
+            None => Header::from_path(path)?,
+        };
+        let data_start = cursor.position() as usize;
+        let contents = if header.run_length {
+            Contents::Decompressed(expand_runs(&contents, data_start, header.precision))
+        } else {
+            contents
+        };
+        Ok(Self {
+            header,
+            data_start,
+            contents,
+        })
+    }
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+    /// Whether the file has a header, rather than being written before headers existed
+    pub fn has_header(&self) -> bool {
+        self.data_start > 0
+    }
+    /// The number of bytes after the header, including any partly written result at the end
+    pub fn data_len(&self) -> u64 {
+        (self.contents.len() - self.data_start) as u64
+    }
+    /// The number of results in the file
+    pub fn num_results(&self) -> u64 {
+        self.data_len() / self.header.precision.size()
+    }
+    /// The result at the given offset, or `None` if it hasn't been written yet
+    pub fn get(&self, offset: u64) -> Option<f32> {
+        let size = self.header.precision.size();
+        let start = self.data_start + (offset * size) as usize;
+        let bytes = self.contents.get(start..start + size as usize)?;
+        Some(self.header.precision.decode(bytes))
+    }
+}
+
+/// Writes results to a ping file, storing each run of timeouts as minus its length if the file is
+/// run-length encoded. This makes scans of sparse ranges much smaller, but the results no longer
+/// line up with the addresses, so the file can't be read at random.
+pub struct ResultWriter<W> {
+    writer: W,
+    run_length: bool,
+    precision: Precision,
+    /// The number of timeouts since the last result was written
+    num_timeouts: u32,
+}
+impl<W: AsyncWrite + Unpin> ResultWriter<W> {
+    /// Write results in the format given by the file's header
+    pub fn new(writer: W, header: &Header) -> Self {
+        Self {
+            writer,
+            run_length: header.run_length,
+            precision: header.precision,
+            num_timeouts: 0,
+        }
+    }
+    /// Write a ping time, or -1 for a timeout
+    pub async fn write(&mut self, rtt: f32) -> io::Result<()> {
+        if !self.run_length {
+            return self.write_value(rtt).await;
+        }
+        if rtt < 0. {
+            self.num_timeouts += 1;
+            if self.num_timeouts == self.precision.max_run_length() {
+                self.write_run().await?;
+            }
+            return Ok(());
+        }
+        self.write_run().await?;
+        self.write_value(rtt).await
+    }
+    /// Write everything so far through to the file, including the run of timeouts that hasn't
+    /// been written yet. A run that goes on after this is written as a separate one.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        self.write_run().await?;
+        self.writer.flush().await
+    }
+    /// Write the run of timeouts that hasn't been written yet and flush the writer. This has to be
+    /// called at the end, or the timeouts at the end of the scan are lost.
+    pub async fn shutdown(&mut self) -> io::Result<()> {
+        self.write_run().await?;
+        self.writer.shutdown().await
+    }
+    async fn write_run(&mut self) -> io::Result<()> {
+        if self.num_timeouts > 0 {
+            self.write_value(-(self.num_timeouts as f32)).await?;
+            self.num_timeouts = 0;
+        }
+        Ok(())
+    }
+    async fn write_value(&mut self, value: f32) -> io::Result<()> {
+        self.writer.write_all(&self.precision.encode(value)).await
+    }
+}
+
+/// How each result is stored in a ping file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Precision {
+    #[default]
+    F32,
+    /// Half the size, and still precise to about 0.1% of each ping time. Timeouts are still -1,
+    /// which an `f16` can store exactly. Ping times past about 18 hours are stored as that.
+    F16,
+}
+impl Precision {
+    /// The number of bytes each result takes up
+    pub fn size(self) -> u64 {
+        match self {
+            Precision::F32 => 4,
+            Precision::F16 => 2,
+        }
+    }
+    /// The longest run of timeouts that can be stored as one result, since longer runs can't be
+    /// stored exactly
+    fn max_run_length(self) -> u32 {
+        match self {
+            Precision::F32 => 1 << 24,
+            Precision::F16 => 1 << 11,
+        }
+    }
+    /// Read a result, which is stored big endian like everything else in the file
+    pub async fn read(self, reader: &mut (impl AsyncRead + Unpin)) -> io::Result<f32> {
+        match self {
+            Precision::F32 => reader.read_f32().await,
+            Precision::F16 => Ok(f16::from_bits(reader.read_u16().await?).to_f32()),
+        }
+    }
+    fn decode(self, bytes: &[u8]) -> f32 {
+        match self {
+            Precision::F32 => f32::from_be_bytes(bytes.try_into().unwrap()),
+            Precision::F16 => f16::from_be_bytes(bytes.try_into().unwrap()).to_f32(),
+        }
+    }
+    fn encode(self, value: f32) -> Vec<u8> {
+        match self {
+            Precision::F32 => value.to_be_bytes().to_vec(),
+            // anything longer than an f16 can hold is stored as the longest it can, not infinity
+            Precision::F16 => f16::from_f32(value.min(f16::MAX.to_f32()))
+                .to_be_bytes()
+                .to_vec(),
+        }
+    }
+}
+
+/// The number of results that a value in a run-length encoded file stands for
+pub fn run_length(value: f32) -> u64 {
+    if value < -1. && value.fract() == 0. {
+        -value as u64
+    } else {
+        1
+    }
+}
+
+/// Expand the runs of timeouts in the contents of a run-length encoded file. The header and any
+/// partly written result at the end are kept as they are.
+fn expand_runs(contents: &[u8], data_start: usize, precision: Precision) -> Vec<u8> {
+    let mut expanded = contents[..data_start].to_vec();
+    let mut values = contents[data_start..].chunks_exact(precision.size() as usize);
+    let timeout = precision.encode(-1.);
+    for bytes in &mut values {
+        match run_length(precision.decode(bytes)) {
+            1 => expanded.extend(bytes),
+            n => (0..n).for_each(|_| expanded.extend(&timeout)),
+        }
+    }
+    expanded.extend(values.remainder());
+    expanded
+}
+
+enum Contents {
+    Mapped(Mmap),
+    Decompressed(Vec<u8>),
+}
+impl Deref for Contents {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match self {
+            Contents::Mapped(mmap) => mmap,
+            Contents::Decompressed(bytes) => bytes,
+        }
+    }
+}
+
+fn invalid_data(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// The default filename for a scan of the range. Ranges with only a few subnets are named after
+/// them, like `10.0.0.0-8_192.168.0.0-16.ping`, and others get a name from a hash of the range,
+/// like `scan_3f9a2c01_146nets.ping`, in which case the range can only be found from the header.
+pub fn path_from_range(mut range: IpRange<Ipv4Net>) -> Result<PathBuf, std::fmt::Error> {
+    range.simplify();
+    let mut name = String::new();
+    let num_nets = range.iter().count();
+    if num_nets > MAX_NAMED_SUBNETS {
+        write!(name, "scan_{:08x}_{num_nets}nets.ping", range_hash(&range))?;
+        return Ok(name.into());
+    }
+    for net in range.iter() {
+        write!(name, "{}-{}_", net.network(), net.prefix_len())?;
+    }
+    name.pop();
+    write!(name, ".ping")?;
+    Ok(name.into())
+}
+
+/// Parse the range out of a filename made by [`path_from_range`], or `None` if the filename
+/// doesn't name a range, like the hashed names of ranges with many subnets
+pub fn parse_range_from_path(path: impl AsRef<Path>) -> Option<IpRange<Ipv4Net>> {
+    // `file_stem` would only take off the last extension, and would cut a name without one at the
+    // last dot of its last address
+    let filename = path.as_ref().file_name()?.to_str()?;
+    let filename = filename.strip_suffix(".gz").unwrap_or(filename);
+    let filename = filename.strip_suffix(".ping").unwrap_or(filename);
+    let mut range = IpRange::<Ipv4Net>::new();
+    for s in filename.split('_') {
+        let s = s.replace('-', "/").parse().ok()?;
+        range.add(s);
+    }
+    range.simplify();
+    Some(range)
+}
+
+/// A 32 bit FNV-1a hash of the subnets in a range, which unlike `std`'s hashers is guaranteed to
+/// stay the same between versions of Rust
+fn range_hash(range: &IpRange<Ipv4Net>) -> u32 {
+    let mut hash = 0x811c9dc5u32;
+    for net in range.iter() {
+        for byte in net.network().octets().into_iter().chain([net.prefix_len()]) {
+            hash ^= byte as u32;
+            hash = hash.wrapping_mul(0x01000193);
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn partly_written_header_is_not_ready() {
+        for len in 0..MAGIC.len() {
+            let mut cursor = Cursor::new(&MAGIC[..len]);
+            let e = Header::read(&mut cursor).await.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        }
+        let header = Header {
+            range: ["10.0.0.0/24".parse().unwrap()].into_iter().collect(),
+            all_addresses: true,
+            run_length: false,
+            precision: Precision::F32,
+            stride: 1,
+        };
+        let bytes = header.to_bytes();
+        let e = Header::read(&mut Cursor::new(&bytes[..bytes.len() - 1]))
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        let read = Header::read(&mut Cursor::new(&bytes[..])).await.unwrap();
+        assert_eq!(read, Some(header));
+    }
+
+    #[tokio::test]
+    async fn file_without_header_is_legacy() {
+        for bytes in [&1.5f32.to_le_bytes()[..], &[0, 0]] {
+            let mut cursor = Cursor::new(bytes);
+            assert_eq!(Header::read(&mut cursor).await.unwrap(), None);
+            assert_eq!(cursor.position(), 0);
+        }
+    }
+
+    fn range(nets: &[&str]) -> IpRange<Ipv4Net> {
+        let mut range = nets
+            .iter()
+            .map(|net| net.parse().unwrap())
+            .collect::<IpRange<_>>();
+        range.simplify();
+        range
+    }
+
+    #[test]
+    fn range_round_trips_through_path() {
+        for nets in [
+            &["10.0.0.0/8"][..],
+            &["0.0.0.0/0"],
+            &["192.168.1.5/32"],
+            &["10.0.0.0/24", "172.16.0.0/12", "192.168.0.0/16"],
+        ] {
+            let range = range(nets);
+            let path = path_from_range(range.clone()).unwrap();
+            assert_eq!(parse_range_from_path(&path), Some(range.clone()));
+            let mut compressed = path.clone().into_os_string();
+            compressed.push(".gz");
+            assert_eq!(parse_range_from_path(compressed), Some(range.clone()));
+            let extensionless = path.file_stem().unwrap();
+            assert_eq!(parse_range_from_path(extensionless), Some(range.clone()));
+            let in_dir = Path::new("scans/2023.06").join(&path);
+            assert_eq!(parse_range_from_path(in_dir), Some(range));
+        }
+    }
+
+    #[test]
+    fn multi_subnet_path_lists_every_subnet() {
+        let path = path_from_range(range(&["10.0.0.0/24", "10.0.2.0/24"])).unwrap();
+        assert_eq!(path, Path::new("10.0.0.0-24_10.0.2.0-24.ping"));
+    }
+
+    #[test]
+    fn hashed_path_has_no_range() {
+        let nets = (0..=MAX_NAMED_SUBNETS)
+            .map(|i| format!("10.{}.0.0/16", i * 2))
+            .collect::<Vec<_>>();
+        let nets = nets.iter().map(String::as_str).collect::<Vec<_>>();
+        let path = path_from_range(range(&nets)).unwrap();
+        let name = path.to_str().unwrap();
+        let expected_suffix = format!("_{}nets.ping", MAX_NAMED_SUBNETS + 1);
+        assert!(name.starts_with("scan_") && name.ends_with(&expected_suffix));
+        assert_eq!(parse_range_from_path(&path), None);
+        let mut compressed = path.into_os_string();
+        compressed.push(".gz");
+        assert_eq!(parse_range_from_path(compressed), None);
+    }
+
+    #[test]
+    fn timeouts_round_trip_exactly() {
+        for precision in [Precision::F32, Precision::F16] {
+            assert_eq!(precision.decode(&precision.encode(-1.)), -1.);
+            for n in [2, 3, 1000, precision.max_run_length()] {
+                let value = -(n as f32);
+                assert_eq!(precision.decode(&precision.encode(value)), value);
+                assert_eq!(run_length(value), n as u64);
+            }
+        }
+    }
+
+    #[test]
+    fn f16_keeps_ping_times_apart_from_timeouts() {
+        let precision = Precision::F16;
+        for rtt in [0., 1e-9, 1e-7, 1e-5, 0.0001, 0.001, 0.25, 1., 5., 30.] {
+            let decoded = precision.decode(&precision.encode(rtt));
+            assert!(
+                decoded.is_finite() && decoded >= 0.,
+                "{rtt} became {decoded}"
+            );
+            assert_eq!(run_length(decoded), 1);
+            // about 0.1% of each ping time, once it's past the subnormals
+            if rtt >= 1e-4 {
+                assert!(
+                    (decoded - rtt).abs() <= rtt * 1e-3,
+                    "{rtt} became {decoded}"
+                );
+            }
+        }
+        for rtt in [65504., 65520., 1e5, f32::MAX, f32::INFINITY] {
+            let decoded = precision.decode(&precision.encode(rtt));
+            assert_eq!(decoded, f16::MAX.to_f32(), "{rtt}");
+        }
+    }
+
+    #[test]
+    fn stride_keeps_offsets_consecutive() {
+        let mut header = Header {
+            range: range(&["10.0.0.0/29", "10.0.1.0/30"]),
+            all_addresses: false,
+            run_length: false,
+            precision: Precision::F32,
+            stride: 3,
+        };
+        let offsets = |header: &Header| {
+            header
+                .addresses_with_offsets()
+                .map(|(offset, addr)| (offset, addr.to_string()))
+                .collect::<Vec<_>>()
+        };
+        // the /29 comes first, see `pinger::addresses`
+        let expected = ["10.0.0.1", "10.0.0.4", "10.0.1.1"];
+        let expected = (0..).zip(expected.map(String::from)).collect::<Vec<_>>();
+        assert_eq!(offsets(&header), expected);
+        header.all_addresses = true;
+        let expected = ["10.0.0.0", "10.0.0.3", "10.0.0.6", "10.0.1.1"];
+        let expected = (0..).zip(expected.map(String::from)).collect::<Vec<_>>();
+        assert_eq!(offsets(&header), expected);
+        header.stride = 1;
+        assert_eq!(header.addresses_with_offsets().count(), 12);
+        assert_eq!(header.addresses_with_offsets().last().unwrap().0, 11);
+    }
+}
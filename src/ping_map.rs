@@ -1,4 +1,16 @@
-use std::{net::Ipv4Addr, path::Path, sync::Arc, time::Duration};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io::Write,
+    net::Ipv4Addr,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
 use bytemuck::bytes_of;
 use egui::{vec2, PaintCallbackInfo, Vec2};
@@ -6,31 +18,125 @@ use ipnet::Ipv4Net;
 use iprange::IpRange;
 use itertools::Itertools;
 use tokio::{
-    fs::File,
-    io::{AsyncReadExt, BufReader},
     sync::mpsc::{UnboundedReceiver, UnboundedSender},
     task::JoinHandle,
 };
 use type_map::concurrent::TypeMap;
 use wgpu::{
-    util::{BufferInitDescriptor, DeviceExt},
+    util::{BufferInitDescriptor, DeviceExt, StagingBelt},
     *,
 };
 
-use crate::{gpu::GpuState, wgpu_ext::BufferVec};
+use crate::{
+    color::{
+        latency_to_color, latency_to_texel, texel_to_latency, Palette, MAX_TEXEL_LATENCY,
+        TEXEL_LATENCY_UNIT,
+    },
+    gpu::GpuState,
+    ping_file::{self, MappedFile, Precision},
+    wgpu_ext::{BufferVec, PassTimer},
+};
+
+/// How fast the arrow keys pan the map, in view widths per second
+const KEY_PAN_SPEED: f32 = 0.5;
+/// A /24 is a square this many addresses wide on the map
+const DENSITY_CELL_WIDTH: u32 = 16;
+/// Each block of the map is a square texture `1 << DEFAULT_BITS_PER_BLOCK` texels wide, so it
+/// holds the results of a /12
+const DEFAULT_BITS_PER_BLOCK: u32 = 16 - 6;
+/// Blocks are packed into square atlas pages at most this many blocks wide
+const MAX_PAGE_WIDTH: u32 = 4;
+/// The size of each chunk of staging memory for uploading instances. Bigger uploads get a chunk of
+/// their own.
+const STAGING_CHUNK_SIZE: u64 = 1 << 20;
+/// The fraction of responders that the auto-fit color range covers, so a few outliers don't wash
+/// out the rest of the map
+const AUTO_FIT_PERCENTILE: f64 = 0.99;
+/// The zoom that fits the whole map in the view
+const MIN_ZOOM: f32 = 1.;
+/// The zoom where a single address fills the view, past which there's nothing more to see
+const MAX_ZOOM: f32 = (1 << 16) as f32;
+/// How many results a second a replay plays at to begin with
+const DEFAULT_REPLAY_SPEED: f64 = 100_000.;
+/// The side length of the minimap in points
+const MINIMAP_SIZE: f32 = 200.;
+/// The gap between the minimap and the corner of the map
+const MINIMAP_MARGIN: f32 = 8.;
+/// The highest cell coordinate on the map, which is a cell per address
+const MAX_CELL: f32 = ((1 << 16) - 1) as f32;
+/// How many results the diff reader makes between letting other tasks run
+const DIFF_YIELD_INTERVAL: usize = 4096;
 
 pub struct Widget {
     state_index: usize,
     instance_rx: Option<UnboundedReceiver<Instance>>,
     file_reader_handle: Option<JoinHandle<()>>,
+    read_progress: Arc<ReadProgress>,
+    /// Roughly how many bytes of GPU memory the map's textures and buffers take up
+    gpu_memory: Arc<AtomicU64>,
     reset: bool,
     pan: Vec2,
     zoom: f32,
+    pub palette: Palette,
+    /// Show only whether each address responded, ignoring its ping time
+    pub binary: bool,
+    pub hide_responders: bool,
+    pub hide_timeouts: bool,
+    /// Draw lines along the boundaries of subnets with this prefix length, which must be even
+    pub grid_prefix: Option<u32>,
+    /// When zoomed out, color each /24 by how many of its addresses responded
+    pub density: bool,
+    /// Whether the map shows the difference between two files rather than a single file
+    diff: bool,
+    /// The smallest size to draw each address when zoomed out, in pixels
+    pub cell_size: f32,
+    pub brightness: f32,
+    pub gamma: f32,
+    /// The ping time in seconds that gets the last color in the palette
+    pub max_latency: f32,
+    /// A copy of what has been sent to the GPU, for looking up results by address
+    texels: Texels,
+    /// The address under the cursor, as of the last frame
+    hovered: Option<Ipv4Addr>,
+    /// The address that the context menu was opened on, which stays the same while it's open
+    context_addr: Option<Ipv4Addr>,
+    /// The rectangle that was shift-dragged out on the map
+    selection: Option<Selection>,
+    /// Show the whole map in the corner, with the part that's in view outlined
+    pub minimap: bool,
+    /// The file that's being played back in scan order, if it's a replay
+    replay: Option<Replay>,
+    /// The range of the last file that was opened, so the view can stay put when a file of the same
+    /// range is opened after it
+    range: Option<IpRange<Ipv4Net>>,
 }
 
 impl Widget {
-    pub fn new(gpu: &GpuState, egui_renderer: &mut egui_wgpu::Renderer) -> Self {
-        let state = State::new(&gpu.device, &gpu.surface_config, gpu.sample_count, 16 - 6);
+    /// With `max_points`, only about that many results are kept for looking up and re-rendering,
+    /// oldest first, on top of what's in the textures
+    pub fn new(
+        gpu: &GpuState,
+        egui_renderer: &mut egui_wgpu::Renderer,
+        max_points: Option<u64>,
+    ) -> Self {
+        let bits_per_block = max_bits_per_block(&gpu.device.limits());
+        if bits_per_block < DEFAULT_BITS_PER_BLOCK {
+            println!(
+                "The GPU's limits only allow blocks of {0}x{0} addresses, which may be slower",
+                1 << bits_per_block
+            );
+        }
+        let mut state = State::new(
+            &gpu.device,
+            &gpu.surface_config,
+            gpu.sample_count,
+            bits_per_block,
+        );
+        state.block_timer = gpu
+            .timestamp_period
+            .map(|period| PassTimer::new(&gpu.device, period, "Rendering the blocks"));
+        state.max_points = max_points;
+        let gpu_memory = state.gpu_memory.clone();
         let state_index = Self::insert_state(&mut egui_renderer.paint_callback_resources, state);
         Self {
             instance_rx: None,
@@ -38,14 +144,74 @@ impl Widget {
             pan: vec2(0., 0.),
             zoom: 1.,
             file_reader_handle: None,
+            read_progress: Arc::default(),
+            gpu_memory,
             reset: false,
+            palette: Palette::default(),
+            binary: false,
+            hide_responders: false,
+            hide_timeouts: false,
+            grid_prefix: None,
+            density: false,
+            diff: false,
+            cell_size: 1.,
+            brightness: 1.,
+            gamma: 1.,
+            max_latency: 0.5,
+            texels: Texels::new(max_points),
+            hovered: None,
+            context_addr: None,
+            selection: None,
+            minimap: false,
+            replay: None,
+            range: None,
         }
     }
     pub fn show(&mut self, ui: &mut egui::Ui) {
         let size = ui.available_size();
+        // the minimap has to claim the clicks and drags over it before the map can
+        let minimap = self.minimap.then(|| {
+            let corner =
+                ui.available_rect_before_wrap().right_bottom() - vec2(1., 1.) * MINIMAP_MARGIN;
+            let rect = egui::Rect::from_min_max(corner - vec2(1., 1.) * MINIMAP_SIZE, corner);
+            ui.interact(rect, ui.id().with("minimap"), egui::Sense::click_and_drag())
+        });
         let (rect, response) = ui.allocate_exact_size(size, egui::Sense::click_and_drag());
 
+        if let Some(ref minimap) = minimap {
+            // pressing or dragging on the minimap centers the map there
+            if let (true, Some(pos)) = (
+                minimap.is_pointer_button_down_on(),
+                minimap.interact_pointer_pos(),
+            ) {
+                self.center_on_cell(cell_at(pos, minimap.rect, [0., 0.], [1., 1.]));
+            }
+        }
+        self.update_range();
         let (pan, zoom) = self.handle_input(ui, rect, &response);
+        self.advance_replay(ui.ctx().input(|i| i.stable_dt));
+        self.hovered = response
+            .hover_pos()
+            .filter(|_| !minimap.as_ref().is_some_and(|m| m.hovered()))
+            .and_then(|pos| addr_at(pos, rect, pan, zoom));
+        self.show_context_menu(&response);
+        self.handle_selection(ui, rect, &response, pan, zoom);
+        let view = ViewUniform {
+            pan,
+            scale: zoom,
+            palette: self.palette as u32,
+            binary: self.binary as u32,
+            hide_responders: self.hide_responders as u32,
+            hide_timeouts: self.hide_timeouts as u32,
+            grid_prefix: self.grid_prefix.unwrap_or(0),
+            density: self.density as u32,
+            diff: self.diff as u32,
+            cell_size: self.cell_size,
+            brightness: self.brightness,
+            gamma: self.gamma,
+            max_latency: self.max_latency,
+            ..Default::default()
+        };
 
         let mut new_instances = vec![];
         if let Some(ref mut rx) = self.instance_rx {
@@ -56,8 +222,18 @@ impl Widget {
 
         let reset = self.reset;
         self.reset = false;
+        if reset {
+            self.texels.clear();
+            self.selection = None;
+        }
+        self.texels.insert(&new_instances);
 
         let get_state = self.state_getter_mut();
+        let minimap_view = ViewUniform {
+            pan: [0., 0.],
+            scale: [1., 1.],
+            ..view
+        };
         let prepare = move |device: &Device,
                             queue: &Queue,
                             encoder: &mut CommandEncoder,
@@ -65,13 +241,20 @@ impl Widget {
             let span = tracing::trace_span!("Prepare Pingmap");
             let _span = span.enter();
             let state = get_state(type_map);
-            state.update_pan_zoom(queue, pan, zoom);
+            // the last frame has been submitted by now
+            if let Some(ref mut timer) = state.block_timer {
+                timer.read();
+            }
+            state.update_view(queue, &view, &minimap_view);
             if reset {
                 state.reset();
             }
             if !new_instances.is_empty() {
                 state.update_instances(device, queue, encoder, &new_instances);
             }
+            if reset || !new_instances.is_empty() {
+                state.update_gpu_memory();
+            }
             vec![]
         };
 
@@ -83,6 +266,53 @@ impl Widget {
                     .paint(self.paint_fn()),
             ),
         });
+        if let Some(ref selection) = self.selection {
+            // outline the whole cells at the corners
+            let min = selection.start.min(selection.end).floor();
+            let max = selection.start.max(selection.end).floor() + vec2(1., 1.);
+            let corners = [min, max].map(|c| cell_to_pos(c, rect, pan, zoom));
+            let stroke = egui::Stroke::new(1.5, egui::Color32::WHITE);
+            ui.painter_at(rect).rect_stroke(
+                egui::Rect::from_two_pos(corners[0], corners[1]),
+                0.,
+                stroke,
+            );
+        }
+        if let Some(minimap) = minimap {
+            self.paint_minimap(ui, minimap.rect, rect, pan, zoom);
+        }
+    }
+    /// Draw the whole map into `minimap_rect`, outlining the part of it that's in `rect`
+    fn paint_minimap(
+        &self,
+        ui: &egui::Ui,
+        minimap_rect: egui::Rect,
+        rect: egui::Rect,
+        pan: [f32; 2],
+        zoom: [f32; 2],
+    ) {
+        let painter = ui.painter_at(minimap_rect);
+        // only the blocks with results are drawn, so the map underneath has to be covered up
+        painter.rect_filled(minimap_rect, 0., egui::Color32::BLACK);
+        let get_state = self.state_getter();
+        painter.add(egui::PaintCallback {
+            rect: minimap_rect,
+            callback: Arc::new(egui_wgpu::CallbackFn::new().paint(
+                move |_, render_pass, type_map| {
+                    get_state(type_map).paint_minimap(render_pass);
+                },
+            )),
+        });
+        let corners = [rect.left_top(), rect.right_bottom()]
+            .map(|pos| cell_at(pos, rect, pan, zoom))
+            .map(|cell| cell_to_pos(cell, minimap_rect, [0., 0.], [1., 1.]));
+        let stroke = egui::Stroke::new(1.5, egui::Color32::WHITE);
+        painter.rect_stroke(egui::Rect::from_two_pos(corners[0], corners[1]), 0., stroke);
+        painter.rect_stroke(
+            minimap_rect,
+            0.,
+            ui.visuals().widgets.noninteractive.bg_stroke,
+        );
     }
     fn handle_input(
         &mut self,
@@ -90,12 +320,26 @@ impl Widget {
         rect: egui::Rect,
         response: &egui::Response,
     ) -> ([f32; 2], [f32; 2]) {
-        if ui.ctx().input(|i| i.key_pressed(egui::Key::Space)) {
+        // only react to the initial press of space, not the key-repeats from holding it down
+        let space_tapped = ui.ctx().input(|i| {
+            i.events.iter().any(|e| {
+                matches!(
+                    e,
+                    egui::Event::Key {
+                        key: egui::Key::Space,
+                        pressed: true,
+                        repeat: false,
+                        ..
+                    }
+                )
+            })
+        });
+        if space_tapped {
             self.zoom = 1.;
             self.pan = vec2(0., 0.);
         }
         // scale x or y down to make it render square
-        let mut scale = vec2(
+        let aspect_scale = vec2(
             1.0f32.min(rect.aspect_ratio().recip()),
             1.0f32.min(rect.aspect_ratio()),
         );
@@ -108,34 +352,395 @@ impl Widget {
             if response.double_clicked() {
                 self.zoom *= 4.;
             }
-            self.zoom = self.zoom.max(1.);
         }
+        self.zoom = self.zoom.clamp(MIN_ZOOM, MAX_ZOOM);
         // apply the zoom to the scale vec
-        scale *= self.zoom;
+        let scale = aspect_scale * self.zoom;
         let screen_to_uv = vec2(2., 2.) / rect.size() / scale;
-        // calculate how much to pan to make the zooming centered on the cursor
+        // pan to keep the point of the map under the cursor where it is
         if let Some(pointer_pos) = ui.ctx().input(|i| i.pointer.hover_pos()) {
-            let factor = self.zoom / last_zoom - 1.;
-            self.pan -= (pointer_pos - rect.center()) * factor * screen_to_uv;
+            let clip = (pointer_pos - rect.center()) / rect.size() * 2.;
+            self.pan = zoom_pan(self.pan, clip, aspect_scale * last_zoom, scale);
+        }
+        // nudge the pan with the arrow keys or WASD, unless a text field has focus
+        if !ui.ctx().wants_keyboard_input() {
+            let (direction, dt) = ui.ctx().input(|i| {
+                let down = |keys: [egui::Key; 2]| keys.iter().any(|k| i.key_down(*k)) as u8 as f32;
+                let direction = vec2(
+                    down([egui::Key::ArrowLeft, egui::Key::A])
+                        - down([egui::Key::ArrowRight, egui::Key::D]),
+                    down([egui::Key::ArrowUp, egui::Key::W])
+                        - down([egui::Key::ArrowDown, egui::Key::S]),
+                );
+                (direction, i.stable_dt)
+            });
+            self.pan += direction * KEY_PAN_SPEED * dt * vec2(2., 2.) / scale;
         }
-        // apply pointer dragging to the pan vec
-        self.pan += response.drag_delta() * screen_to_uv;
+        // apply pointer dragging to the pan vec, unless it's selecting a region instead
+        if ui.ctx().input(|i| !i.modifiers.shift) {
+            self.pan += response.drag_delta() * screen_to_uv;
+        }
+        // keep the edges of the map from going past the center of the view, so it can't be lost
+        self.pan = self.pan.clamp(vec2(-1., -1.), vec2(1., 1.));
         let mut pan = self.pan;
         // invert y because of coordinate differences
         pan.y *= -1.;
         (pan.into(), scale.into())
     }
+    /// Shift-dragging selects a rectangle of the map, and the results in it are tallied once the
+    /// drag is done. Diffs don't have results to tally.
+    fn handle_selection(
+        &mut self,
+        ui: &egui::Ui,
+        rect: egui::Rect,
+        response: &egui::Response,
+        pan: [f32; 2],
+        scale: [f32; 2],
+    ) {
+        if self.diff {
+            return;
+        }
+        let cell = response
+            .interact_pointer_pos()
+            .map(|pos| cell_at(pos, rect, pan, scale).clamp(Vec2::ZERO, Vec2::splat(MAX_CELL)));
+        if let Some(cell) = cell {
+            if response.drag_started() && ui.ctx().input(|i| i.modifiers.shift) {
+                self.selection = Some(Selection {
+                    start: cell,
+                    end: cell,
+                    ranges: vec![],
+                    stats: None,
+                });
+            }
+        }
+        let Some(ref mut selection) = self.selection else {
+            return;
+        };
+        if selection.stats.is_some() {
+            return;
+        }
+        if let (true, Some(cell)) = (response.dragged(), cell) {
+            selection.end = cell;
+        }
+        if response.drag_released() {
+            let min = selection.start.min(selection.end).floor();
+            let max = selection.start.max(selection.end).floor();
+            selection.ranges =
+                hilbert_ranges([min.x as u32, min.y as u32], [max.x as u32, max.y as u32]);
+            selection.stats = Some(self.texels.region_stats(&selection.ranges));
+        }
+    }
+    /// The tally of the results in the selected region, once it has been selected
+    pub fn selection_stats(&self) -> Option<&RegionStats> {
+        self.selection.as_ref()?.stats.as_ref()
+    }
+    pub fn clear_selection(&mut self) {
+        self.selection = None;
+    }
+    /// Write the responders in the selected region to a CSV file with their ping times, which are
+    /// only as precise as the map's colors. Returns how many were written.
+    pub fn export_selection(&self, path: &Path) -> std::io::Result<u64> {
+        let ranges = match self.selection {
+            Some(ref selection) => &selection.ranges[..],
+            None => &[],
+        };
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(writer, "ip,rtt_ms")?;
+        let mut num_written = 0;
+        for (addr, texel) in self.texels.region_texels(ranges) {
+            if texel == 255 {
+                continue;
+            }
+            let rtt_ms = texel_to_latency(texel) * 1000.;
+            writeln!(writer, "{},{rtt_ms:.3}", Ipv4Addr::from(addr))?;
+            num_written += 1;
+        }
+        writer.flush()?;
+        Ok(num_written)
+    }
+    /// Show the menu for the address that was right-clicked, unless it doesn't have a result
+    fn show_context_menu(&mut self, response: &egui::Response) {
+        if response.secondary_clicked() {
+            self.context_addr = self
+                .hovered
+                .filter(|addr| self.texels.get(u32::from(*addr)) != 0);
+        }
+        let Some(addr) = self.context_addr else {
+            return;
+        };
+        let result = self.describe_result(addr);
+        response.clone().context_menu(|ui| {
+            if ui.button("Copy address").clicked() {
+                ui.output_mut(|o| o.copied_text = addr.to_string());
+                ui.close_menu();
+            }
+            if ui.button("Copy address and result").clicked() {
+                ui.output_mut(|o| o.copied_text = format!("{addr}: {result}"));
+                ui.close_menu();
+            }
+            if ui.button("Center here").clicked() {
+                self.center_on(addr);
+                ui.close_menu();
+            }
+        });
+    }
+    /// Pan so that the address is in the middle of the view, keeping the zoom
+    fn center_on(&mut self, addr: Ipv4Addr) {
+        let coords = addr_to_coords(u32::from(addr), 16).map(|c| c as f32 + 0.5);
+        self.center_on_cell(vec2(coords[0], coords[1]));
+    }
+    /// Pan so that a point on the map, in cells from the bottom left, is in the center of the view
+    fn center_on_cell(&mut self, cell: Vec2) {
+        // the inverse of `cell_at` for the center of the widget
+        let map = cell / (1 << 16) as f32 * 2. - vec2(1., 1.);
+        self.pan = vec2(-map.x, map.y);
+    }
+    /// Roughly how many bytes of GPU memory the loaded map takes up
+    pub fn gpu_memory(&self) -> u64 {
+        self.gpu_memory.load(Ordering::Relaxed)
+    }
+    /// How far the map is zoomed in, where 1 fits the whole map in the view
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+    /// The address under the cursor, if it's over the map
+    pub fn hovered(&self) -> Option<Ipv4Addr> {
+        self.hovered
+    }
+    /// Describe the result shown for an address, like "23 ms" or "timed out"
+    pub fn describe_result(&self, addr: Ipv4Addr) -> String {
+        let texel = self.texels.get(u32::from(addr));
+        if self.diff {
+            // the inverse of `diff_texel`
+            return match texel {
+                0 => "no result".into(),
+                1 => "down both times".into(),
+                2 => "stopped responding".into(),
+                3 => "started responding".into(),
+                4 => "<= -250 ms".into(),
+                254 => ">= +250 ms".into(),
+                _ => format!("{:+.0} ms", (texel as f32 - 129.) / 125. * 250.),
+            };
+        }
+        match texel {
+            0 => "no result".into(),
+            254 => format!(">= {MAX_TEXEL_LATENCY} s"),
+            255 => "timed out".into(),
+            _ => format!("{:.1} ms", texel_to_latency(texel) * 1000.),
+        }
+    }
+    /// Whether the address responded, which is never the case in a diff
+    pub fn responded(&self, addr: Ipv4Addr) -> bool {
+        !self.diff && matches!(self.texels.get(u32::from(addr)), 1..=254)
+    }
+    /// The color the map shows for an address, or `None` if it doesn't have a result or this is
+    /// a diff
+    pub fn result_color(&self, addr: Ipv4Addr) -> Option<[u8; 4]> {
+        if self.diff {
+            return None;
+        }
+        match self.texels.get(u32::from(addr)) {
+            0 => None,
+            255 => Some(latency_to_color(-1., self.max_latency, self.palette)),
+            texel => Some(latency_to_color(
+                texel_to_latency(texel),
+                self.max_latency,
+                self.palette,
+            )),
+        }
+    }
+    /// Fit the color range to the ping times that have been loaded. Does nothing for a diff, or if
+    /// nothing has responded yet.
+    pub fn auto_fit_latency(&mut self) {
+        if self.diff {
+            return;
+        }
+        if let Some(texel) = self.texels.responder_percentile(AUTO_FIT_PERCENTILE) {
+            self.max_latency = texel_to_latency(texel).max(TEXEL_LATENCY_UNIT);
+        }
+    }
+    /// Open a file, keeping the view where it is if the file is of the same range as the last one
     pub fn open_file(&mut self, path: impl AsRef<Path>) {
-        self.zoom = 1.;
-        self.pan = vec2(0., 0.);
+        self.start_file_reader(path.as_ref().to_path_buf(), None);
+    }
+    /// Open a file to watch it fill in, in the order it was scanned. It starts out playing, and
+    /// ends up the same as opening it normally.
+    pub fn replay_file(&mut self, path: impl AsRef<Path>) {
+        let path = path.as_ref().to_path_buf();
+        let allowed = Arc::new(AtomicU64::new(0));
+        self.start_file_reader(path.clone(), Some(allowed.clone()));
+        self.replay = Some(Replay {
+            path,
+            allowed,
+            position: 0.,
+            playing: true,
+            speed: DEFAULT_REPLAY_SPEED,
+        });
+    }
+    /// With `allowed`, the reader only sends that many results, which a replay moves along
+    fn start_file_reader(&mut self, path: PathBuf, allowed: Option<Arc<AtomicU64>>) {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let progress = Arc::new(ReadProgress::default());
+        let reader = {
+            let progress = progress.clone();
+            async move {
+                if let Err(e) = file_reader(path, tx, progress.clone(), allowed).await {
+                    *progress.error.lock().unwrap() = Some(e);
+                }
+            }
+        };
+        self.start_reader(rx, progress, reader);
+        self.diff = false;
+    }
+    pub fn is_replaying(&self) -> bool {
+        self.replay.is_some()
+    }
+    /// Show the play/pause button, the timeline, and the speed of the replay, if there is one
+    pub fn replay_controls(&mut self, ui: &mut egui::Ui) {
+        let total = self.read_progress.total.load(Ordering::Relaxed);
+        let Some(ref mut replay) = self.replay else {
+            return;
+        };
+        let mut seek_to = None;
+        let at_end = total > 0 && replay.position >= total as f64;
+        let play_label = if replay.playing { "Pause" } else { "Play" };
+        if ui.button(play_label).clicked() {
+            // playing again from the end starts over
+            if at_end {
+                seek_to = Some(0.);
+            }
+            replay.playing = !replay.playing;
+        }
+        let mut position = replay.position;
+        let label = format!("{} / {total}", position as u64);
+        let timeline = ui.add(
+            egui::Slider::new(&mut position, 0.0..=total as f64)
+                .show_value(false)
+                .text(label),
+        );
+        if timeline.changed() {
+            seek_to = Some(position);
+        }
+        ui.add(
+            egui::Slider::new(&mut replay.speed, 1e2..=1e8)
+                .logarithmic(true)
+                .suffix(" results/s")
+                .text("Speed"),
+        );
+        let stopped = ui.button("Skip to End").clicked();
+        if let Some(position) = seek_to {
+            self.seek_replay(position);
+        }
+        if stopped {
+            self.stop_replay();
+        }
+    }
+    /// Move the replay along by however many results it plays in `dt` seconds
+    fn advance_replay(&mut self, dt: f32) {
+        let total = self.read_progress.total.load(Ordering::Relaxed);
+        let Some(ref mut replay) = self.replay else {
+            return;
+        };
+        if !replay.playing {
+            return;
+        }
+        replay.position += replay.speed * dt as f64;
+        // the total isn't known until the reader has read the header
+        if total > 0 && replay.position >= total as f64 {
+            replay.position = total as f64;
+            replay.playing = false;
+        }
+        replay
+            .allowed
+            .store(replay.position as u64, Ordering::Relaxed);
+    }
+    /// Jump to a point in the replay. Going back starts the file over, since results can't be
+    /// taken back off the map, but the view stays where it is.
+    fn seek_replay(&mut self, position: f64) {
+        let Some(mut replay) = self.replay.take() else {
+            return;
+        };
+        if position < self.read_progress.num_read.load(Ordering::Relaxed) as f64 {
+            replay.allowed = Arc::new(AtomicU64::new(0));
+            self.start_file_reader(replay.path.clone(), Some(replay.allowed.clone()));
+            self.reset = true;
+        }
+        replay.position = position;
+        replay.allowed.store(position as u64, Ordering::Relaxed);
+        self.replay = Some(replay);
+    }
+    /// Let the rest of the file through at once, leaving it open like any other file
+    pub fn stop_replay(&mut self) {
+        if let Some(replay) = self.replay.take() {
+            replay.allowed.store(u64::MAX, Ordering::Relaxed);
+        }
+    }
+    /// Show how the results changed between two complete scans of the same range
+    pub fn open_diff(&mut self, before: MappedFile, after: MappedFile) {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let progress = Arc::new(ReadProgress::default());
+        let reader = diff_reader(before, after, tx, progress.clone());
+        self.start_reader(rx, progress, reader);
+        self.diff = true;
+    }
+    /// The fraction of the file that has been read, or `None` if it isn't being read
+    pub fn load_progress(&self) -> Option<f32> {
+        let handle = self.file_reader_handle.as_ref()?;
+        if handle.is_finished() {
+            return None;
+        }
+        let num_read = self.read_progress.num_read.load(Ordering::Relaxed);
+        let total = self.read_progress.total.load(Ordering::Relaxed);
+        Some(num_read as f32 / total.max(1) as f32)
+    }
+    /// The error that stopped the file from being read, if there was one. Each error is only
+    /// returned once.
+    pub fn take_load_error(&self) -> Option<std::io::Error> {
+        self.read_progress.error.lock().unwrap().take()
+    }
+    /// Stop reading the file, but keep showing what has been read so far
+    pub fn cancel_load(&mut self) {
+        if let Some(handle) = self.file_reader_handle.take() {
+            handle.abort();
+        }
+    }
+    /// Stop reading the current file and clear the map
+    pub fn close(&mut self) {
+        if let Some(handle) = self.file_reader_handle.take() {
+            handle.abort();
+        }
+        self.instance_rx = None;
+        self.reset = true;
+        self.diff = false;
+        self.replay = None;
+    }
+    /// Replace whatever is being shown with the instances from a new reader task
+    fn start_reader(
+        &mut self,
+        instance_rx: UnboundedReceiver<Instance>,
+        read_progress: Arc<ReadProgress>,
+        reader: impl Future<Output = ()> + Send + 'static,
+    ) {
+        self.replay = None;
         if let Some(handle) = self.file_reader_handle.take() {
             handle.abort();
             self.reset = true;
         }
-        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
-        self.instance_rx = Some(rx);
-        let handle = tokio::spawn(file_reader(path.as_ref().to_path_buf(), tx));
-        self.file_reader_handle = Some(handle);
+        self.instance_rx = Some(instance_rx);
+        self.read_progress = read_progress;
+        self.file_reader_handle = Some(tokio::spawn(reader));
+    }
+    /// Once the reader has read the range of its file, go back to showing the whole map if the
+    /// range isn't the same as the last file's
+    fn update_range(&mut self) {
+        let Some(range) = self.read_progress.range.lock().unwrap().take() else {
+            return;
+        };
+        if self.range.as_ref() != Some(&range) {
+            self.zoom = 1.;
+            self.pan = vec2(0., 0.);
+        }
+        self.range = Some(range);
     }
     fn paint_fn(
         &self,
@@ -168,14 +773,41 @@ impl Widget {
 
 struct State {
     render_pipeline: RenderPipeline,
-    pan_zoom_buffer: Buffer,
-    pan_zoom_bind_group: BindGroup,
+    grid_pipeline: RenderPipeline,
+    view_buffer: Buffer,
+    view_bind_group: BindGroup,
+    /// The view for the minimap, which always shows the whole map
+    minimap_view_buffer: Buffer,
+    minimap_view_bind_group: BindGroup,
+    empty_bind_group: BindGroup,
     blocks: Vec<Option<Block>>,
+    /// The textures that the blocks' texels are stored in, which are added as blocks are
+    pages: Vec<AtlasPage>,
+    /// The width of each atlas page in blocks
+    page_width: u32,
+    /// The number of blocks that have been given a place in the atlas
+    num_slots_used: u32,
     texture_bind_group_layout: BindGroupLayout,
+    /// Renders instances into the blocks
+    block_pipeline: RenderPipeline,
+    block_index_bind_group_layout: BindGroupLayout,
+    /// Builds the mip levels of the pages, see mip.wgsl
+    mip_pipeline: RenderPipeline,
+    mip_bind_group_layout: BindGroupLayout,
     bits_per_block: u32,
     bits_per_block_bind_group: Arc<BindGroup>,
-    bits_per_block_bind_group_layout: BindGroupLayout,
     next_to_clear: usize,
+    /// Staging memory for uploading instances, which is reused between frames instead of being
+    /// allocated for every upload. It's only in a mutex because the callback resources have to be `Sync`.
+    staging_belt: Mutex<StagingBelt>,
+    /// Times rendering the new instances into the block textures with `--gpu-profile`
+    block_timer: Option<PassTimer>,
+    /// How many instances the blocks' buffers can hold before the oldest blocks are flushed early
+    max_points: Option<u64>,
+    /// How many instances are in the blocks' buffers
+    num_points: u64,
+    /// The size of the pages and the blocks' buffers, shared with the widget
+    gpu_memory: Arc<AtomicU64>,
 }
 impl State {
     fn update_instances(
@@ -185,75 +817,179 @@ impl State {
         encoder: &mut CommandEncoder,
         instances: &[Instance],
     ) {
-        let modified = self.push_instances(device, queue, instances);
+        // the encoder of the last upload has been submitted by now, so its chunks can be reused
+        // once the GPU has copied out of them
+        self.staging_belt.get_mut().unwrap().recall();
+        let mut modified = self.push_instances(device, queue, encoder, instances);
+        self.staging_belt.get_mut().unwrap().finish();
+        // a block shows up more than once if its instances weren't all together, but it only has
+        // to be rendered once
+        modified.sort_unstable();
+        modified.dedup();
+        // each block is rendered once, and the last one is the highest for clearing up to
+        debug_assert!(modified.windows(2).all(|w| w[0] < w[1]));
+        if let Some(ref mut timer) = self.block_timer {
+            timer.begin(encoder);
+        }
         for i in &modified {
-            let bits_per_block_bind_group = self.bits_per_block_bind_group.clone();
-            self.get_block_mut(device, *i)
-                .render(encoder, &bits_per_block_bind_group);
+            let block = self.blocks[*i].as_mut().unwrap();
+            let page = &self.pages[block.page];
+            block.render(
+                encoder,
+                &self.block_pipeline,
+                &self.bits_per_block_bind_group,
+                page,
+            );
+            page.update_mips(encoder, &self.mip_pipeline, block.offset, block.side_length);
+        }
+        if let Some(ref mut timer) = self.block_timer {
+            timer.end(encoder);
         }
         if let Some(last) = modified.last() {
             for i in self.next_to_clear..*last {
-                if let Some(block) = &mut self.blocks[i] {
-                    block.instance_buffers.clear();
+                self.flush_block(i);
+            }
+            // results that arrive out of order mustn't move this back over flushed blocks
+            self.next_to_clear = self.next_to_clear.max(*last);
+        }
+        // everything in the buffers has been rendered by now, so flushing blocks that are still
+        // getting results only means that later results are drawn over them instead. Results are
+        // read in address order, so the lowest blocks have had theirs the longest.
+        if let Some(max_points) = self.max_points {
+            for i in 0..self.blocks.len() {
+                if self.num_points <= max_points {
+                    break;
                 }
+                self.flush_block(i);
             }
-            self.next_to_clear = *last;
+        }
+    }
+    fn update_gpu_memory(&self) {
+        let pages = self.pages.iter().map(|p| p.gpu_memory).sum::<u64>();
+        let blocks = self
+            .blocks
+            .iter()
+            .flatten()
+            .map(|b| b.instance_buffers.allocated_size())
+            .sum::<u64>();
+        self.gpu_memory.store(pages + blocks, Ordering::Relaxed);
+    }
+    fn flush_block(&mut self, index: usize) {
+        if let Some(block) = &mut self.blocks[index] {
+            self.num_points -= block.instance_buffers.num_items() as u64;
+            block.flush();
         }
     }
     pub fn push_instances(
         &mut self,
         device: &Device,
         queue: &Queue,
+        encoder: &mut CommandEncoder,
         instances: &[Instance],
     ) -> Vec<usize> {
-        let block_size = 2usize.pow(self.bits_per_block).pow(2);
+        let block_bits = 2 * self.bits_per_block;
+        let block_size = 2usize.pow(block_bits);
         let instance_groups = instances
-            .into_iter()
+            .iter()
             .group_by(|i| i.address as usize / block_size);
         let mut modified = vec![];
         for (block_index, instances) in instance_groups.into_iter() {
             modified.push(block_index);
-            let block = self.get_block_mut(device, block_index);
+            self.get_block_mut(device, queue, block_index);
+            let block = self.blocks[block_index].as_mut().unwrap();
             let instances = instances.copied().collect::<Vec<_>>();
-            block.instance_buffers.extend(device, queue, &instances);
+            block.update_density(queue, &self.pages[block.page], &instances);
+            let instances = instances
+                .into_iter()
+                .map(|i| PackedInstance::pack(i, block_bits))
+                .collect::<Vec<_>>();
+            self.num_points += instances.len() as u64;
+            block.instance_buffers.extend(
+                device,
+                encoder,
+                self.staging_belt.get_mut().unwrap(),
+                &instances,
+            );
         }
         modified
     }
-    fn update_pan_zoom(&mut self, queue: &Queue, pan: [f32; 2], scale: [f32; 2]) {
-        queue.write_buffer(
-            &self.pan_zoom_buffer,
-            0,
-            bytes_of(&PanZoomUniform { pan, scale }),
-        );
+    fn update_view(&mut self, queue: &Queue, view: &ViewUniform, minimap_view: &ViewUniform) {
+        queue.write_buffer(&self.view_buffer, 0, bytes_of(view));
+        queue.write_buffer(&self.minimap_view_buffer, 0, bytes_of(minimap_view));
     }
     fn paint<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        self.paint_blocks(render_pass, &self.view_bind_group);
+        // the grid shader discards everything when it's turned off
+        render_pass.set_pipeline(&self.grid_pipeline);
+        render_pass.set_bind_group(0, &self.view_bind_group, &[]);
+        render_pass.draw(0..6, 0..1);
+    }
+    fn paint_minimap<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        self.paint_blocks(render_pass, &self.minimap_view_bind_group);
+    }
+    fn paint_blocks<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        view_bind_group: &'a BindGroup,
+    ) {
         render_pass.set_pipeline(&self.render_pipeline);
         render_pass.set_bind_group(0, &self.bits_per_block_bind_group, &[]);
-        render_pass.set_bind_group(1, &self.pan_zoom_bind_group, &[]);
-        for block in self.blocks.iter().filter_map(|m| m.as_ref()) {
-            render_pass.set_bind_group(2, &block.block_index_bind_group, &[]);
-            render_pass.set_bind_group(3, &block.texture_bind_group, &[]);
-            render_pass.draw(0..6, 0..1);
+        render_pass.set_bind_group(1, &self.empty_bind_group, &[]);
+        render_pass.set_bind_group(2, view_bind_group, &[]);
+        for page in &self.pages {
+            render_pass.set_bind_group(3, &page.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, page.blocks_buffer.slice(..));
+            render_pass.draw(0..6, 0..page.num_blocks);
         }
     }
-    fn get_block_mut(&mut self, device: &Device, index: usize) -> &mut Block {
-        let maybe_block = &mut self.blocks[index];
-        if maybe_block.is_none() {
-            *maybe_block = Some(Block::new(
+    fn get_block_mut(&mut self, device: &Device, queue: &Queue, index: usize) -> &mut Block {
+        if self.blocks[index].is_none() {
+            // blocks fill the pages in the order they're first needed
+            let side_length = 2u32.pow(self.bits_per_block);
+            let slots_per_page = self.page_width.pow(2);
+            let page = (self.num_slots_used / slots_per_page) as usize;
+            let slot = self.num_slots_used % slots_per_page;
+            self.num_slots_used += 1;
+            if page == self.pages.len() {
+                self.pages.push(AtlasPage::new(
+                    device,
+                    &self.texture_bind_group_layout,
+                    &self.mip_bind_group_layout,
+                    self.page_width,
+                    side_length,
+                ));
+            }
+            let offset = [
+                slot % self.page_width * side_length,
+                slot / self.page_width * side_length,
+            ];
+            self.pages[page].push_block(
+                queue,
+                BlockInstance {
+                    offset,
+                    index: index as _,
+                },
+            );
+            self.blocks[index] = Some(Block::new(
                 device,
                 index as _,
-                &self.texture_bind_group_layout,
-                &self.bits_per_block_bind_group_layout,
-                2u32.pow(self.bits_per_block),
+                page,
+                offset,
+                &self.block_index_bind_group_layout,
+                side_length,
             ));
         }
-        maybe_block.as_mut().unwrap()
+        self.blocks[index].as_mut().unwrap()
     }
     fn reset(&mut self) {
         for block in &mut self.blocks {
             *block = None;
         }
+        // new pages start out empty, unlike the old ones
+        self.pages.clear();
+        self.num_slots_used = 0;
         self.next_to_clear = 0;
+        self.num_points = 0;
     }
     fn new(
         device: &Device,
@@ -261,6 +997,9 @@ impl State {
         sample_count: u32,
         bits_per_block: u32,
     ) -> Self {
+        // Packed instances only have room for a 24 bit address offset within their block, and the
+        // density view needs at least a whole /24 in each block
+        assert!((4..=12).contains(&bits_per_block));
         let shader_module = device.create_shader_module(include_wgsl!("shader.wgsl"));
         let bits_per_block_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Bits per Block Buffer"),
@@ -289,67 +1028,87 @@ impl State {
             }],
             label: Some("Bits per Block Group"),
         }));
-        let pan_zoom_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Pan Zoom Buffer"),
-            contents: bytes_of(&PanZoomUniform::default()),
+        let view_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("View Buffer"),
+            contents: bytes_of(&ViewUniform::default()),
             usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
         });
-        let pan_zoom_bind_group_layout =
-            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                entries: &[BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::VERTEX,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("Pan Zoom Bind Group Layout"),
-            });
-        let pan_zoom_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            layout: &pan_zoom_bind_group_layout,
+        let view_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("View Bind Group Layout"),
+        });
+        let view_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &view_bind_group_layout,
             entries: &[BindGroupEntry {
                 binding: 0,
-                resource: pan_zoom_buffer.as_entire_binding(),
+                resource: view_buffer.as_entire_binding(),
             }],
-            label: Some("Pan Zoom Bind Group"),
+            label: Some("View Bind Group"),
+        });
+        let minimap_view_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Minimap View Buffer"),
+            contents: bytes_of(&ViewUniform::default()),
+            usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
+        });
+        let minimap_view_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &view_bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: minimap_view_buffer.as_entire_binding(),
+            }],
+            label: Some("Minimap View Bind Group"),
+        });
+        // the block index is only used when rendering into the blocks, so the map leaves it empty
+        let empty_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[],
+            label: Some("Empty Bind Group Layout"),
+        });
+        let empty_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &empty_bind_group_layout,
+            entries: &[],
+            label: Some("Empty Bind Group"),
         });
-        let block_index_bind_group_layout =
-            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                entries: &[BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::VERTEX,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("Block Index Bind Group Layout"),
-            });
         let texture_bind_group_layout =
             device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                entries: &[BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::FRAGMENT,
-                    ty: BindingType::Texture {
-                        sample_type: TextureSampleType::Uint,
-                        view_dimension: TextureViewDimension::D2,
-                        multisampled: false,
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Uint,
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Uint,
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                ],
                 label: Some("Texture Bind Group Layout"),
             });
         let pipeline_layout_desc = PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
             bind_group_layouts: &[
                 &bits_per_block_bind_group_layout,
-                &pan_zoom_bind_group_layout,
-                &block_index_bind_group_layout,
+                &empty_bind_group_layout,
+                &view_bind_group_layout,
                 &texture_bind_group_layout,
             ],
             push_constant_ranges: &[],
@@ -358,7 +1117,7 @@ impl State {
         let vertex_state = VertexState {
             module: &shader_module,
             entry_point: "vs_main",
-            buffers: &[Instance::desc()],
+            buffers: &[BlockInstance::desc()],
         };
         let primitive_state = PrimitiveState {
             topology: PrimitiveTopology::TriangleList,
@@ -394,6 +1153,128 @@ impl State {
             multiview: None,
         };
         let render_pipeline = device.create_render_pipeline(&render_pipeline_desc);
+        let grid_shader_module = device.create_shader_module(include_wgsl!("grid.wgsl"));
+        let grid_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Grid Pipeline Layout"),
+            bind_group_layouts: &[&view_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let grid_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Grid Pipeline"),
+            layout: Some(&grid_pipeline_layout),
+            vertex: VertexState {
+                module: &grid_shader_module,
+                entry_point: "vs_grid",
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &grid_shader_module,
+                entry_point: "fs_grid",
+                targets: &[Some(ColorTargetState {
+                    format: surface_config.format,
+                    blend: Some(BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: primitive_state,
+            depth_stencil: None,
+            multisample: multisample_state,
+            multiview: None,
+        });
+        let mip_shader_module = device.create_shader_module(include_wgsl!("mip.wgsl"));
+        let mip_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Uint,
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+            label: Some("Mip Bind Group Layout"),
+        });
+        let mip_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Mip Pipeline Layout"),
+            bind_group_layouts: &[&mip_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let mip_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Mip Pipeline"),
+            layout: Some(&mip_pipeline_layout),
+            vertex: VertexState {
+                module: &mip_shader_module,
+                entry_point: "vs_mip",
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &mip_shader_module,
+                entry_point: "fs_mip",
+                targets: &[Some(ColorTargetState {
+                    format: TextureFormat::R8Uint,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: primitive_state,
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: 1,
+                ..multisample_state
+            },
+            multiview: None,
+        });
+        // every block is rendered with the same pipeline, so it's only built once
+        let block_index_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("Block Index Bind Group Layout"),
+            });
+        let block_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Block Render Pipeline Layout"),
+            bind_group_layouts: &[
+                &bits_per_block_bind_group_layout,
+                &block_index_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let block_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Block Render Pipeline"),
+            layout: Some(&block_pipeline_layout),
+            vertex: VertexState {
+                module: &shader_module,
+                entry_point: "vs_block",
+                buffers: &[PackedInstance::desc()],
+            },
+            fragment: Some(FragmentState {
+                module: &shader_module,
+                entry_point: "fs_block",
+                targets: &[Some(ColorTargetState {
+                    format: TextureFormat::R8Uint,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: primitive_state,
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: 1,
+                ..multisample_state
+            },
+            multiview: None,
+        });
+        let page_width =
+            (device.limits().max_texture_dimension_2d >> bits_per_block).clamp(1, MAX_PAGE_WIDTH);
         let num_blocks = 2usize.pow(16 - bits_per_block).pow(2);
         let mut blocks = Vec::with_capacity(num_blocks);
         for _ in 0..num_blocks {
@@ -401,40 +1282,305 @@ impl State {
         }
         Self {
             render_pipeline,
-            pan_zoom_buffer,
-            pan_zoom_bind_group,
+            grid_pipeline,
+            view_buffer,
+            view_bind_group,
+            minimap_view_buffer,
+            minimap_view_bind_group,
+            empty_bind_group,
             blocks,
+            pages: vec![],
+            page_width,
+            num_slots_used: 0,
             texture_bind_group_layout,
+            block_pipeline,
+            block_index_bind_group_layout,
+            mip_pipeline,
+            mip_bind_group_layout,
             bits_per_block_bind_group,
-            bits_per_block_bind_group_layout,
             bits_per_block,
             next_to_clear: 0,
+            staging_belt: Mutex::new(StagingBelt::new(STAGING_CHUNK_SIZE)),
+            block_timer: None,
+            max_points: None,
+            num_points: 0,
+            gpu_memory: Arc::default(),
         }
     }
 }
 
 #[tracing::instrument(skip_all)]
-async fn file_reader(path: impl AsRef<Path>, instance_tx: UnboundedSender<Instance>) {
-    let file = File::open(&path).await.unwrap();
-    let mut buf_reader = BufReader::new(file);
-    let nets = range_from_path(path).iter().collect_vec();
-    let instances = nets.iter().flat_map(Ipv4Net::hosts).map(Instance::from);
+async fn file_reader(
+    path: impl AsRef<Path>,
+    instance_tx: UnboundedSender<Instance>,
+    progress: Arc<ReadProgress>,
+    allowed: Option<Arc<AtomicU64>>,
+) -> std::io::Result<()> {
+    // compressed files can't be followed while they're written, since the stream isn't valid until
+    // the scan finishes, so they're only read up to the end
+    let compressed = ping_file::is_compressed(&path);
     let poll_dur = Duration::from_millis(10);
-    for mut instance in instances {
-        let val = read_f32_wait(&mut buf_reader, poll_dur).await.unwrap();
-        if val >= 0. {
-            instance.time = (val / 0.5 * 255.).clamp(0., 255.) as u32;
-            instance_tx.send(instance).unwrap();
-        }else{
-            instance.time = 255;
+    // a file that was just created may not have its whole header yet
+    let (header, mut buf_reader) = loop {
+        match ping_file::open(&path).await {
+            Err(e) if !compressed && e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                tokio::time::sleep(poll_dur).await
+            }
+            opened => break opened?,
+        }
+    };
+    let total = header.addresses().count() as u64;
+    progress.total.store(total, Ordering::Relaxed);
+    *progress.range.lock().unwrap() = Some(header.range.clone());
+    let mut instances = header.addresses().map(Instance::from).peekable();
+    while instances.peek().is_some() {
+        let val = if compressed {
+            match header.precision.read(&mut buf_reader).await {
+                Ok(val) => val,
+                Err(_) => break,
+            }
+        } else {
+            read_result_wait(&mut buf_reader, header.precision, poll_dur).await?
+        };
+        let count = if header.run_length {
+            ping_file::run_length(val)
+        } else {
+            1
+        };
+        for mut instance in instances.by_ref().take(count as usize) {
+            if let Some(ref allowed) = allowed {
+                while progress.num_read.load(Ordering::Relaxed) >= allowed.load(Ordering::Relaxed) {
+                    tokio::time::sleep(poll_dur).await;
+                }
+            }
+            // 0 is reserved for addresses without a result and 255 for timeouts
+            instance.time = if val >= 0. {
+                latency_to_texel(val)
+            } else {
+                255
+            };
             instance_tx.send(instance).unwrap();
+            progress.num_read.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    Ok(())
+}
+
+/// Combine the results of two scans of the same range into instances that show how each address
+/// changed, for as many addresses as both files have results for
+#[tracing::instrument(skip_all)]
+async fn diff_reader(
+    before: MappedFile,
+    after: MappedFile,
+    instance_tx: UnboundedSender<Instance>,
+    progress: Arc<ReadProgress>,
+) {
+    let num_results = before.num_results().min(after.num_results());
+    progress.total.store(num_results, Ordering::Relaxed);
+    *progress.range.lock().unwrap() = Some(before.header().range.clone());
+    let addrs = before.header().addresses_with_offsets();
+    for (i, (offset, addr)) in addrs.take(num_results as usize).enumerate() {
+        // Nothing else here awaits, so give the runtime a chance to run other tasks and to abort
+        // this one every so often
+        if i % DIFF_YIELD_INTERVAL == 0 {
+            tokio::task::yield_now().await;
+        }
+        let mut instance = Instance::from(addr);
+        instance.time = diff_texel(before.get(offset).unwrap(), after.get(offset).unwrap());
+        // the widget has moved on to something else
+        if instance_tx.send(instance).is_err() {
+            return;
+        }
+        progress.num_read.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The texels of every block, stored in chunks of 2^16 addresses that are only allocated once
+/// they have a result
+struct Texels {
+    chunks: Vec<Option<Box<[u8]>>>,
+    /// The allocated chunks, oldest first
+    allocated: VecDeque<usize>,
+    /// How many chunks can be allocated before the oldest is dropped
+    max_chunks: Option<usize>,
+    /// The number of addresses with each texel value, not counting those without a result. Results
+    /// in dropped chunks are still counted.
+    counts: [u64; 256],
+}
+impl Texels {
+    /// Keep at most about `max_points` texels, rounded up to whole chunks
+    fn new(max_points: Option<u64>) -> Self {
+        Self {
+            chunks: vec![],
+            allocated: VecDeque::new(),
+            max_chunks: max_points.map(|max| ((max + 0xffff) >> 16) as usize),
+            counts: [0; 256],
+        }
+    }
+    fn insert(&mut self, instances: &[Instance]) {
+        if self.chunks.is_empty() {
+            self.chunks.resize_with(1 << 16, || None);
+        }
+        for instance in instances {
+            let chunk_index = (instance.address >> 16) as usize;
+            if self.chunks[chunk_index].is_none() {
+                if self.max_chunks == Some(self.allocated.len()) {
+                    let oldest = self.allocated.pop_front().unwrap();
+                    self.chunks[oldest] = None;
+                }
+                self.allocated.push_back(chunk_index);
+            }
+            let chunk =
+                self.chunks[chunk_index].get_or_insert_with(|| vec![0; 1 << 16].into_boxed_slice());
+            let texel = &mut chunk[(instance.address & 0xffff) as usize];
+            // addresses only get a result once, except when a reader is restarted
+            if *texel != 0 {
+                self.counts[*texel as usize] -= 1;
+            }
+            self.counts[instance.time as usize] += 1;
+            *texel = instance.time;
+        }
+    }
+    /// The texel that the given fraction of the responders are at or below
+    fn responder_percentile(&self, fraction: f64) -> Option<u8> {
+        let responders = &self.counts[1..=254];
+        let total = responders.iter().sum::<u64>();
+        if total == 0 {
+            return None;
+        }
+        let target = (total as f64 * fraction).ceil() as u64;
+        let mut seen = 0;
+        for (i, count) in responders.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return Some(i as u8 + 1);
+            }
         }
+        Some(254)
     }
+    fn get(&self, address: u32) -> u8 {
+        match self.chunks.get((address >> 16) as usize) {
+            Some(Some(chunk)) => chunk[(address & 0xffff) as usize],
+            _ => 0,
+        }
+    }
+    fn clear(&mut self) {
+        *self = Self {
+            max_chunks: self.max_chunks,
+            ..Self::new(None)
+        };
+    }
+    /// The addresses in the ranges that have a result, along with their texels
+    fn region_texels<'a>(
+        &'a self,
+        ranges: &'a [Range<u64>],
+    ) -> impl Iterator<Item = (u32, u8)> + 'a {
+        ranges.iter().flat_map(move |range| {
+            (range.start >> 16..(range.end + 0xffff) >> 16).flat_map(move |chunk_index| {
+                let chunk = match self.chunks.get(chunk_index as usize) {
+                    Some(Some(chunk)) => &chunk[..],
+                    _ => &[],
+                };
+                let chunk_start = chunk_index << 16;
+                let start = range.start.max(chunk_start) - chunk_start;
+                let end = range.end.min(chunk_start + (1 << 16)) - chunk_start;
+                let texels = chunk.get(start as usize..end as usize).unwrap_or_default();
+                (start as u32..)
+                    .zip(texels)
+                    .filter(|(_, &texel)| texel != 0)
+                    .map(move |(i, &texel)| (chunk_start as u32 + i, texel))
+            })
+        })
+    }
+    /// Tally the results of the addresses in the ranges
+    fn region_stats(&self, ranges: &[Range<u64>]) -> RegionStats {
+        let latencies: [f32; 256] = std::array::from_fn(|t| match t {
+            1..=254 => texel_to_latency(t as u8),
+            _ => 0.,
+        });
+        let mut stats = RegionStats {
+            num_addrs: ranges.iter().map(|r| r.end - r.start).sum(),
+            ..Default::default()
+        };
+        let mut total_rtt = 0.;
+        for (_, texel) in self.region_texels(ranges) {
+            stats.num_results += 1;
+            if texel != 255 {
+                stats.num_responders += 1;
+                total_rtt += latencies[texel as usize] as f64;
+            }
+        }
+        if stats.num_responders > 0 {
+            stats.mean_rtt = Some((total_rtt / stats.num_responders as f64) as f32);
+        }
+        stats
+    }
+}
+
+/// A rectangle of the map, in cells from the bottom left
+struct Selection {
+    start: Vec2,
+    end: Vec2,
+    /// The address ranges that make up the rectangle, once it's been selected
+    ranges: Vec<Range<u64>>,
+    stats: Option<RegionStats>,
 }
 
-async fn read_f32_wait(buf_reader: &mut BufReader<File>, dur: Duration) -> std::io::Result<f32> {
+/// A tally of the results in a region of the map
+#[derive(Debug, Default)]
+pub struct RegionStats {
+    pub num_addrs: u64,
+    pub num_results: u64,
+    pub num_responders: u64,
+    /// The mean ping time of the responders in seconds
+    pub mean_rtt: Option<f32>,
+}
+
+/// A file being fed to the map at a controllable speed
+struct Replay {
+    path: PathBuf,
+    /// How many results the reader can send so far
+    allowed: Arc<AtomicU64>,
+    /// How far through the file the replay is, in results
+    position: f64,
+    playing: bool,
+    /// How many results a second are let through while it's playing
+    speed: f64,
+}
+
+/// How far a reader task has got through its file
+#[derive(Default)]
+struct ReadProgress {
+    num_read: AtomicU64,
+    total: AtomicU64,
+    /// Why the reader stopped early, until the widget takes it
+    error: Mutex<Option<std::io::Error>>,
+    /// The range of the file once its header has been read, until the widget takes it
+    range: Mutex<Option<IpRange<Ipv4Net>>>,
+}
+
+/// Encode the change in an address's result for `fs_main`. 1 means it didn't respond either time,
+/// 2 means it stopped responding, 3 means it started responding, and 4 to 254 is the change in
+/// ping time with no change at 129.
+fn diff_texel(before: f32, after: f32) -> u8 {
+    match (before >= 0., after >= 0.) {
+        (false, false) => 1,
+        (true, false) => 2,
+        (false, true) => 3,
+        (true, true) => {
+            let delta = ((after - before) / 0.25).clamp(-1., 1.);
+            (129. + delta * 125.).round() as u8
+        }
+    }
+}
+
+async fn read_result_wait(
+    buf_reader: &mut ping_file::Reader,
+    precision: Precision,
+    dur: Duration,
+) -> std::io::Result<f32> {
     loop {
-        match buf_reader.read_f32().await {
+        match precision.read(buf_reader).await {
             Ok(val) => return Ok(val),
             Err(e) if e.kind() != std::io::ErrorKind::UnexpectedEof => return Err(e),
             _ => {}
@@ -443,25 +1589,30 @@ async fn read_f32_wait(buf_reader: &mut BufReader<File>, dur: Duration) -> std::
     }
 }
 
-fn range_from_path(path: impl AsRef<Path>) -> IpRange<Ipv4Net> {
-    let filename = path.as_ref().file_stem().unwrap().to_str().unwrap();
-    let mut range = IpRange::<Ipv4Net>::new();
-    for s in filename.split('_') {
-        let s = s.replace('-', "/").parse().unwrap();
-        range.add(s);
+#[derive(Debug, Copy, Clone)]
+pub struct Instance {
+    pub address: u32,
+    pub time: u8,
+}
+impl From<Ipv4Addr> for Instance {
+    fn from(addr: Ipv4Addr) -> Self {
+        Self {
+            address: u32::from_be_bytes(addr.octets()),
+            time: 0,
+        }
     }
-    range.simplify();
-    range
 }
 
+/// The GPU representation of an [`Instance`]. The time is stored in the high byte and the address
+/// is stored as an offset from the start of its block in the low bits, so each instance only
+/// takes up 4 bytes of vertex buffer.
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct Instance {
-    pub address: u32,
-    pub time: u32,
+pub struct PackedInstance {
+    packed: u32,
 }
-impl Instance {
-    const ATTRS: [VertexAttribute; 2] = vertex_attr_array![0 => Uint32, 1 => Uint32];
+impl PackedInstance {
+    const ATTRS: [VertexAttribute; 1] = vertex_attr_array![0 => Uint32];
     pub fn desc() -> VertexBufferLayout<'static> {
         VertexBufferLayout {
             array_stride: std::mem::size_of::<Self>() as BufferAddress,
@@ -469,64 +1620,101 @@ impl Instance {
             attributes: &Self::ATTRS,
         }
     }
-}
-impl From<Ipv4Addr> for Instance {
-    fn from(addr: Ipv4Addr) -> Self {
+    /// Pack an instance whose block is made up of `2^block_bits` addresses. The offset has to fit
+    /// under the time, so blocks can't have more than `2^24` addresses.
+    pub fn pack(instance: Instance, block_bits: u32) -> Self {
+        debug_assert!(block_bits <= 24);
+        let offset = instance.address & ((1 << block_bits) - 1);
         Self {
-            address: u32::from_be_bytes(addr.octets()),
-            time: u32::from_be_bytes([0, 0, 0, 0]),
+            packed: ((instance.time as u32) << 24) | offset,
         }
     }
 }
 
+/// Everything about how the map is displayed, which can change every frame without touching the
+/// block textures. Its size has to stay a multiple of 16 bytes to be used as a uniform.
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct PanZoomUniform {
+pub struct ViewUniform {
     pan: [f32; 2],
     scale: [f32; 2],
+    palette: u32,
+    binary: u32,
+    hide_responders: u32,
+    hide_timeouts: u32,
+    grid_prefix: u32,
+    density: u32,
+    diff: u32,
+    cell_size: f32,
+    brightness: f32,
+    gamma: f32,
+    max_latency: f32,
+    _padding: u32,
 }
-impl Default for PanZoomUniform {
+impl Default for ViewUniform {
     fn default() -> Self {
         Self {
             pan: [0., 0.],
             scale: [1., 1.],
+            palette: Palette::default() as u32,
+            binary: 0,
+            hide_responders: 0,
+            hide_timeouts: 0,
+            grid_prefix: 0,
+            density: 0,
+            diff: 0,
+            cell_size: 1.,
+            brightness: 1.,
+            gamma: 1.,
+            max_latency: 0.5,
+            _padding: 0,
         }
     }
 }
 
-pub struct Block {
-    texture: Texture,
-    texture_bind_group: BindGroup,
-    render_pipeline: RenderPipeline,
-    instance_buffers: BufferVec<Instance>,
-    block_index_bind_group: BindGroup,
+/// A texture that blocks are packed into, along with the density texture for the same blocks, so
+/// the map doesn't need textures and a bind group for every block
+pub struct AtlasPage {
+    density_texture: Texture,
+    /// A view of each mip level of the texture, which can only be rendered into one at a time
+    mip_views: Vec<TextureView>,
+    /// Binds each mip level but the last for building the one after it
+    mip_bind_groups: Vec<BindGroup>,
+    bind_group: BindGroup,
+    /// Where each block in the page is, in the order they were added
+    blocks_buffer: Buffer,
+    num_blocks: u32,
+    /// The size of the textures and the blocks buffer in bytes
+    gpu_memory: u64,
 }
-impl Block {
-    pub fn new(
+impl AtlasPage {
+    /// Make a page `page_width` blocks wide, for blocks `block_side_length` texels wide
+    fn new(
         device: &Device,
-        index: u32,
         texture_bind_group_layout: &BindGroupLayout,
-        bits_per_block_bind_group_layout: &BindGroupLayout,
-        side_length: u32,
+        mip_bind_group_layout: &BindGroupLayout,
+        page_width: u32,
+        block_side_length: u32,
     ) -> Self {
-        let num_slots = side_length.pow(2);
-        let max_buffer_size =
-            std::mem::size_of::<Instance>() as BufferAddress * num_slots as BufferAddress;
-        let block_index_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Block Index Buffer"),
-            contents: bytes_of(&index),
-            usage: BufferUsages::UNIFORM,
+        let side_length = page_width * block_side_length;
+        let blocks_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Atlas Page Blocks Buffer"),
+            size: std::mem::size_of::<BlockInstance>() as BufferAddress
+                * page_width.pow(2) as BufferAddress,
+            usage: BufferUsages::COPY_DST | BufferUsages::VERTEX,
+            mapped_at_creation: false,
         });
-        let instance_buffers = BufferVec::new(max_buffer_size);
         let texture_format = TextureFormat::R8Uint;
+        // the levels go down to a texel per block, so no texel of any level is shared by two blocks
+        let mip_level_count = block_side_length.trailing_zeros() + 1;
         let texture_desc = TextureDescriptor {
-            label: Some("Block Texture"),
+            label: Some("Atlas Page Texture"),
             size: Extent3d {
                 width: side_length,
                 height: side_length,
                 depth_or_array_layers: 1,
             },
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: TextureDimension::D2,
             format: texture_format,
@@ -534,106 +1722,249 @@ impl Block {
             view_formats: &[texture_format],
         };
         let texture = device.create_texture(&texture_desc);
-        let shader_module = device.create_shader_module(include_wgsl!("shader.wgsl"));
-        let block_index_bind_group_layout =
-            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                entries: &[BindGroupLayoutEntry {
+        let density_side_length = side_length / DENSITY_CELL_WIDTH;
+        let density_texture = device.create_texture(&TextureDescriptor {
+            label: Some("Atlas Page Density Texture"),
+            size: Extent3d {
+                width: density_side_length,
+                height: density_side_length,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
+            ..texture_desc
+        });
+        let mip_views = (0..mip_level_count)
+            .map(|level| {
+                texture.create_view(&TextureViewDescriptor {
+                    base_mip_level: level,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        let mip_bind_groups = mip_views[..mip_views.len() - 1]
+            .iter()
+            .map(|view| {
+                device.create_bind_group(&BindGroupDescriptor {
+                    layout: mip_bind_group_layout,
+                    entries: &[BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(view),
+                    }],
+                    label: Some("Mip Bind Group"),
+                })
+            })
+            .collect();
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: texture_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
                     binding: 0,
-                    visibility: ShaderStages::VERTEX,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                    resource: BindingResource::TextureView(
+                        &texture.create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(
+                        &density_texture.create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+            ],
+            label: Some("Texture Bind Group"),
+        });
+        // every level of both textures has a byte per texel
+        let texture_size = (0..mip_level_count)
+            .map(|level| (side_length as u64 >> level).pow(2))
+            .sum::<u64>()
+            + (density_side_length as u64).pow(2);
+        let gpu_memory = texture_size + blocks_buffer.size();
+        Self {
+            density_texture,
+            mip_views,
+            mip_bind_groups,
+            bind_group,
+            blocks_buffer,
+            num_blocks: 0,
+            gpu_memory,
+        }
+    }
+    /// Add a block to the ones drawn from this page
+    fn push_block(&mut self, queue: &Queue, block: BlockInstance) {
+        let offset = std::mem::size_of::<BlockInstance>() as BufferAddress * self.num_blocks as u64;
+        queue.write_buffer(&self.blocks_buffer, offset, bytes_of(&block));
+        self.num_blocks += 1;
+    }
+    /// Rebuild the mip levels of the block at `offset` from its full size texels
+    fn update_mips(
+        &self,
+        encoder: &mut CommandEncoder,
+        mip_pipeline: &RenderPipeline,
+        offset: [u32; 2],
+        block_side_length: u32,
+    ) {
+        for (level, view) in self.mip_views.iter().enumerate().skip(1) {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Load,
+                        store: true,
                     },
-                    count: None,
-                }],
-                label: Some("Block Index Bind Group Layout"),
+                })],
+                depth_stencil_attachment: None,
             });
+            let [x, y] = offset.map(|o| (o >> level) as f32);
+            let side_length = (block_side_length >> level) as f32;
+            render_pass.set_viewport(x, y, side_length, side_length, 0., 1.);
+            render_pass.set_pipeline(mip_pipeline);
+            render_pass.set_bind_group(0, &self.mip_bind_groups[level - 1], &[]);
+            render_pass.draw(0..6, 0..1);
+        }
+    }
+}
+
+/// Which addresses a block has and where its texels are in its atlas page. Each page has one of
+/// these for every block in it, so all of its blocks can be drawn at once.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BlockInstance {
+    offset: [u32; 2],
+    index: u32,
+}
+impl BlockInstance {
+    const ATTRS: [VertexAttribute; 2] = vertex_attr_array![1 => Uint32x2, 2 => Uint32];
+    pub fn desc() -> VertexBufferLayout<'static> {
+        VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as BufferAddress,
+            step_mode: VertexStepMode::Instance,
+            attributes: &Self::ATTRS,
+        }
+    }
+}
+
+pub struct Block {
+    /// The atlas page that the block's texels are in
+    page: usize,
+    /// Where the block's texels start in its page
+    offset: [u32; 2],
+    side_length: u32,
+    /// The number of responders and results for each /24 in the block, in texel order
+    density_counts: Vec<(u16, u16)>,
+    instance_buffers: BufferVec<PackedInstance>,
+    block_index_bind_group: BindGroup,
+}
+impl Block {
+    pub fn new(
+        device: &Device,
+        index: u32,
+        page: usize,
+        offset: [u32; 2],
+        block_index_bind_group_layout: &BindGroupLayout,
+        side_length: u32,
+    ) -> Self {
+        let num_slots = side_length.pow(2);
+        let max_buffer_size =
+            std::mem::size_of::<PackedInstance>() as BufferAddress * num_slots as BufferAddress;
+        let block_index_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Block Index Buffer"),
+            contents: bytes_of(&index),
+            usage: BufferUsages::UNIFORM,
+        });
+        let instance_buffers = BufferVec::new(max_buffer_size);
+        let density_side_length = side_length / DENSITY_CELL_WIDTH;
         let block_index_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            layout: &block_index_bind_group_layout,
+            layout: block_index_bind_group_layout,
             entries: &[BindGroupEntry {
                 binding: 0,
                 resource: block_index_buffer.as_entire_binding(),
             }],
             label: Some("Block Index Bind Group"),
         });
-        let pipeline_layout_desc = PipelineLayoutDescriptor {
-            label: Some("Block Render Pipeline Layout"),
-            bind_group_layouts: &[bits_per_block_bind_group_layout],
-            push_constant_ranges: &[],
-        };
-        let render_pipeline_layout = device.create_pipeline_layout(&pipeline_layout_desc);
-        let vertex_state = VertexState {
-            module: &shader_module,
-            entry_point: "vs_block",
-            buffers: &[Instance::desc()],
-        };
-        let primitive_state = PrimitiveState {
-            topology: PrimitiveTopology::TriangleList,
-            strip_index_format: None,
-            front_face: FrontFace::Ccw,
-            cull_mode: None,
-            unclipped_depth: false,
-            polygon_mode: PolygonMode::Fill,
-            conservative: false,
-        };
-        let fragment_state = FragmentState {
-            module: &shader_module,
-            entry_point: "fs_block",
-            targets: &[Some(ColorTargetState {
-                format: texture_format,
-                blend: None,
-                write_mask: ColorWrites::ALL,
-            })],
-        };
-        let multisample_state = MultisampleState {
-            count: 1,
-            mask: !0,
-            alpha_to_coverage_enabled: false,
-        };
-        let render_pipeline_desc = RenderPipelineDescriptor {
-            label: Some("Block Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: vertex_state,
-            fragment: Some(fragment_state),
-            primitive: primitive_state,
-            depth_stencil: None,
-            multisample: multisample_state,
-            multiview: None,
-        };
-        let render_pipeline = device.create_render_pipeline(&render_pipeline_desc);
-        let texture_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            layout: texture_bind_group_layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: BindingResource::TextureView(
-                    &texture.create_view(&TextureViewDescriptor::default()),
-                ),
-            }],
-            label: Some("Texture Bind Group"),
-        });
         Self {
-            texture,
-            render_pipeline,
+            page,
+            offset,
+            side_length,
+            density_counts: vec![(0, 0); density_side_length.pow(2) as usize],
             instance_buffers,
             block_index_bind_group,
-            texture_bind_group,
         }
     }
-    pub fn render(&mut self, encoder: &mut CommandEncoder, pan_zoom_bind_group: &BindGroup) {
-        let view = self.texture.create_view(&TextureViewDescriptor::default());
+    /// Free the instances that have already been rendered into the texture. Results are read in
+    /// address order, so this is done once a block is behind the ones getting new results, but
+    /// anything that still arrives for it is rendered on top of what's there.
+    pub fn flush(&mut self) {
+        self.instance_buffers.clear();
+    }
+    /// Count the new results towards their /24s and upload the new densities to the block's part
+    /// of its page
+    pub fn update_density(&mut self, queue: &Queue, page: &AtlasPage, instances: &[Instance]) {
+        let side_length = self.side_length;
+        let density_side_length = side_length / DENSITY_CELL_WIDTH;
+        for instance in instances {
+            let [x, y] = addr_to_coords(instance.address, 16);
+            // the texture's rows go down while the map's y goes up
+            let texel_x = x % side_length / DENSITY_CELL_WIDTH;
+            let texel_y = (side_length - 1 - y % side_length) / DENSITY_CELL_WIDTH;
+            let (responders, results) =
+                &mut self.density_counts[(texel_y * density_side_length + texel_x) as usize];
+            *results += 1;
+            if instance.time != 255 {
+                *responders += 1;
+            }
+        }
+        // 0 is reserved for /24s without any results, like in the block texture
+        let texels = self
+            .density_counts
+            .iter()
+            .map(|&(responders, results)| match results {
+                0 => 0,
+                _ => 1 + (responders as u32 * 253 / results as u32) as u8,
+            })
+            .collect::<Vec<_>>();
+        queue.write_texture(
+            ImageCopyTexture {
+                texture: &page.density_texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: self.offset[0] / DENSITY_CELL_WIDTH,
+                    y: self.offset[1] / DENSITY_CELL_WIDTH,
+                    z: 0,
+                },
+                aspect: TextureAspect::All,
+            },
+            &texels,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(density_side_length),
+                rows_per_image: None,
+            },
+            Extent3d {
+                width: density_side_length,
+                height: density_side_length,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+    /// Render the block's instances into its part of its page's full size mip level. The rest of the
+    /// page is left alone, so what's already there is drawn over rather than cleared.
+    pub fn render(
+        &mut self,
+        encoder: &mut CommandEncoder,
+        render_pipeline: &RenderPipeline,
+        bits_per_block_bind_group: &BindGroup,
+        page: &AtlasPage,
+    ) {
         let render_pass_desc = RenderPassDescriptor {
             label: None,
             color_attachments: &[Some(RenderPassColorAttachment {
-                view: &view,
+                view: &page.mip_views[0],
                 resolve_target: None,
                 ops: Operations {
-                    load: LoadOp::Clear(Color {
-                        r: 0.,
-                        g: 0.,
-                        b: 0.,
-                        a: 0.,
-                    }),
+                    load: LoadOp::Load,
                     store: true,
                 },
             })],
@@ -641,8 +1972,12 @@ impl Block {
         };
         {
             let mut render_pass = encoder.begin_render_pass(&render_pass_desc);
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, pan_zoom_bind_group, &[]);
+            let [x, y] = self.offset.map(|o| o as f32);
+            let side_length = self.side_length as f32;
+            render_pass.set_viewport(x, y, side_length, side_length, 0., 1.);
+            render_pass.set_pipeline(render_pipeline);
+            render_pass.set_bind_group(0, bits_per_block_bind_group, &[]);
+            render_pass.set_bind_group(1, &self.block_index_bind_group, &[]);
             for (buffer, num_occupied) in &self.instance_buffers {
                 render_pass.set_vertex_buffer(0, buffer.slice(..));
                 render_pass.draw(0..6, 0..*num_occupied as _);
@@ -650,3 +1985,232 @@ impl Block {
         }
     }
 }
+
+/// The biggest blocks that fit in the device's limits, up to the default size. Each block has a
+/// texture with a texel per address and instance buffers big enough to hold all of its addresses.
+fn max_bits_per_block(limits: &Limits) -> u32 {
+    let fits = |bits: u32| {
+        let side_length = 1 << bits;
+        let buffer_size = std::mem::size_of::<PackedInstance>() as u64 * side_length * side_length;
+        side_length <= limits.max_texture_dimension_2d as u64
+            && buffer_size <= limits.max_buffer_size
+    };
+    // `State::new` can't go below 4 bits, which any device can handle
+    (4..=DEFAULT_BITS_PER_BLOCK)
+        .rev()
+        .find(|&bits| fits(bits))
+        .unwrap_or(4)
+}
+
+/// The position of an address on the map, the same as `addr_to_coords` in the shader. `bits` is the
+/// order of the Hilbert curve, which is the number of bits in each coordinate, so the low `2 * bits`
+/// bits of `d` are used. A whole IPv4 address needs 16.
+fn addr_to_coords(mut d: u32, bits: u32) -> [u32; 2] {
+    let mut out = [0, 0];
+    let mut s = 1;
+    while s < 1 << bits {
+        let rx = 1 & (d / 2);
+        let ry = 1 & (d ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                out = [s - 1 - out[0], s - 1 - out[1]];
+            }
+            out.swap(0, 1);
+        }
+        out[0] |= s * rx;
+        out[1] |= s * ry;
+        d >>= 2;
+        s <<= 1;
+    }
+    out
+}
+
+/// The pan that keeps the point of the map at `clip` where it is when the scale goes from
+/// `last_scale` to `scale`. Going by `cell_at`, that point is at `clip / scale - pan` along x, and
+/// at the negative of that along y, so it stays put if the pan changes by as much as `clip / scale`
+/// does. Each axis has its own scale, so this holds whatever the aspect ratio.
+fn zoom_pan(pan: Vec2, clip: Vec2, last_scale: Vec2, scale: Vec2) -> Vec2 {
+    pan + clip / scale - clip / last_scale
+}
+
+/// The address at a point in the widget, given the pan and scale sent to the shader
+fn addr_at(pos: egui::Pos2, rect: egui::Rect, pan: [f32; 2], scale: [f32; 2]) -> Option<Ipv4Addr> {
+    let coords = cell_at(pos, rect, pan, scale);
+    if coords.min_elem() < 0. || coords.max_elem() >= (1 << 16) as f32 {
+        return None;
+    }
+    let addr = coords_to_addr([coords.x as u32, coords.y as u32], 16);
+    Some(Ipv4Addr::from(addr))
+}
+
+/// The position on the map at a point in the widget, in cells from the bottom left of the map
+fn cell_at(pos: egui::Pos2, rect: egui::Rect, pan: [f32; 2], scale: [f32; 2]) -> Vec2 {
+    // undo `apply_pan_zoom` to get the position on the map, which goes from -1 to 1 on both axes
+    let clip = (pos - rect.center()) / rect.size() * 2.;
+    let map = vec2(clip.x / scale[0] - pan[0], -clip.y / scale[1] - pan[1]);
+    (map + vec2(1., 1.)) / 2. * (1 << 16) as f32
+}
+
+/// The inverse of `cell_at`
+fn cell_to_pos(cell: Vec2, rect: egui::Rect, pan: [f32; 2], scale: [f32; 2]) -> egui::Pos2 {
+    let map = cell / (1 << 16) as f32 * 2. - vec2(1., 1.);
+    let clip = vec2((map.x + pan[0]) * scale[0], -(map.y + pan[1]) * scale[1]);
+    rect.center() + clip * rect.size() / 2.
+}
+
+/// The address ranges of the cells in a rectangle of the map, from `min` to `max` inclusive. Every
+/// aligned square of the curve is a range of addresses, so the rectangle is split into the biggest
+/// squares that fit in it, which only have to be small along its edges.
+fn hilbert_ranges(min: [u32; 2], max: [u32; 2]) -> Vec<Range<u64>> {
+    fn visit(corner: [u32; 2], size: u32, min: [u32; 2], max: [u32; 2], out: &mut Vec<Range<u64>>) {
+        let far = corner.map(|c| c + (size - 1));
+        if (0..2).any(|i| corner[i] > max[i] || far[i] < min[i]) {
+            return;
+        }
+        if (0..2).all(|i| corner[i] >= min[i] && far[i] <= max[i]) {
+            let len = size as u64 * size as u64;
+            let start = coords_to_addr(corner, 16) as u64 / len * len;
+            out.push(start..start + len);
+            return;
+        }
+        let half = size / 2;
+        for [dx, dy] in [[0, 0], [half, 0], [0, half], [half, half]] {
+            visit([corner[0] + dx, corner[1] + dy], half, min, max, out);
+        }
+    }
+    let mut ranges = vec![];
+    visit([0, 0], 1 << 16, min, max, &mut ranges);
+    // neighbouring squares are often next to each other on the curve too
+    ranges.sort_unstable_by_key(|r| r.start);
+    ranges.dedup_by(|next, prev| {
+        if next.start == prev.end {
+            prev.end = next.end;
+            true
+        } else {
+            false
+        }
+    });
+    ranges
+}
+
+/// The inverse of `addr_to_coords` for a curve of the same order, where both coordinates are below
+/// `1 << bits`
+fn coords_to_addr([mut x, mut y]: [u32; 2], bits: u32) -> u32 {
+    let n = 1 << bits;
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+        d += s * s * ((3 * rx) ^ ry);
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zooming_keeps_the_point_under_the_cursor() {
+        // a wide view, so the axes are scaled differently
+        let rect = egui::Rect::from_min_size(egui::pos2(10., 20.), vec2(1600., 900.));
+        let aspect_scale = vec2(
+            1f32.min(rect.aspect_ratio().recip()),
+            1f32.min(rect.aspect_ratio()),
+        );
+        for pointer in [egui::pos2(10., 20.), egui::pos2(1200., 300.), rect.center()] {
+            for (pan, last_zoom, zoom) in [
+                (vec2(0., 0.), 1., 4.),
+                (vec2(0.3, -0.6), 2.5, 1.1),
+                (vec2(-0.9, 0.2), 100., 180.),
+            ] {
+                let clip = (pointer - rect.center()) / rect.size() * 2.;
+                let (last_scale, scale) = (aspect_scale * last_zoom, aspect_scale * zoom);
+                let new_pan = zoom_pan(pan, clip, last_scale, scale);
+                // the shader gets the pan with y flipped, like `handle_input` returns it
+                let cell =
+                    |pan: Vec2, scale: Vec2| cell_at(pointer, rect, [pan.x, -pan.y], scale.into());
+                let before = cell(pan, last_scale);
+                let after = cell(new_pan, scale);
+                assert!(
+                    (before - after).length() < 1e-2,
+                    "{before:?} moved to {after:?} zooming from {last_zoom} to {zoom}"
+                );
+            }
+        }
+    }
+
+    /// Undo `PackedInstance::pack` the way `vs_block` in the shader does, given the index of the
+    /// block that the instance was drawn into
+    fn shader_unpack(packed: PackedInstance, block_index: u32, bits_per_block: u32) -> Instance {
+        Instance {
+            address: (block_index << (2 * bits_per_block)) | (packed.packed & 0xffffff),
+            time: (packed.packed >> 24) as u8,
+        }
+    }
+
+    #[test]
+    fn packed_instance_round_trips() {
+        // from the smallest blocks `State::new` allows to the biggest that fit in the packing
+        for bits_per_block in 4..=12 {
+            let block_bits = 2 * bits_per_block;
+            let block_size = 1u64 << block_bits;
+            let last_block = ((1u64 << 32) / block_size - 1) as u32;
+            for block_index in [0, 1, last_block] {
+                let start = block_index << block_bits;
+                let last = start + (block_size - 1) as u32;
+                for address in [start, start + 1, last] {
+                    for time in [0, 1, 254, 255] {
+                        let instance = Instance { address, time };
+                        let packed = PackedInstance::pack(instance, block_bits);
+                        let unpacked = shader_unpack(packed, block_index, bits_per_block);
+                        assert_eq!(unpacked.address, address, "{bits_per_block} bits");
+                        assert_eq!(unpacked.time, time, "{bits_per_block} bits");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Every address of the smaller orders, and a spread of them for a whole IPv4 address
+    fn curve_points(bits: u32) -> Box<dyn Iterator<Item = u32>> {
+        if bits <= 8 {
+            Box::new(0..1 << (2 * bits))
+        } else {
+            let step = 0x0001_0001;
+            Box::new((0..u32::MAX / step).flat_map(move |i| [i * step, i * step + 1]))
+        }
+    }
+
+    #[test]
+    fn hilbert_curve_round_trips() {
+        for bits in (1..=8).chain([16]) {
+            for d in curve_points(bits) {
+                let coords = addr_to_coords(d, bits);
+                assert!(coords.iter().all(|&c| c < 1 << bits), "{d} at order {bits}");
+                assert_eq!(coords_to_addr(coords, bits), d, "order {bits}");
+            }
+        }
+    }
+
+    #[test]
+    fn hilbert_curve_is_continuous() {
+        for bits in (1..=8).chain([16]) {
+            for d in curve_points(bits).filter(|&d| d < u32::MAX >> (32 - 2 * bits)) {
+                let [x0, y0] = addr_to_coords(d, bits);
+                let [x1, y1] = addr_to_coords(d + 1, bits);
+                let distance = x0.abs_diff(x1) + y0.abs_diff(y1);
+                assert_eq!(distance, 1, "{d} and {} at order {bits}", d + 1);
+            }
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::Ipv4Addr,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// How long the cursor has to stay on an address before its hostname is looked up, so sweeping
+/// across the map doesn't send a query for every address on the way
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Looks up the hostnames of hovered addresses in the background, remembering every answer
+pub struct ReverseDns {
+    pub enabled: bool,
+    /// The hostname of each address that has been looked up, or `None` if it doesn't have one
+    names: HashMap<Ipv4Addr, Option<String>>,
+    in_flight: HashSet<Ipv4Addr>,
+    /// The address the cursor is on and since when
+    hovered: Option<(Ipv4Addr, Instant)>,
+    tx: UnboundedSender<(Ipv4Addr, Option<String>)>,
+    rx: UnboundedReceiver<(Ipv4Addr, Option<String>)>,
+}
+impl Default for ReverseDns {
+    fn default() -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Self {
+            enabled: false,
+            names: HashMap::new(),
+            in_flight: HashSet::new(),
+            hovered: None,
+            tx,
+            rx,
+        }
+    }
+}
+impl ReverseDns {
+    /// Describe the hostname of a hovered address, like "example.com" or "(no PTR)", or `None` if
+    /// it isn't known yet. This has to be called every frame that the address is hovered, and
+    /// starts looking it up once it has been hovered for long enough.
+    pub fn hostname(&mut self, addr: Ipv4Addr, ctx: &egui::Context) -> Option<String> {
+        while let Ok((addr, name)) = self.rx.try_recv() {
+            self.in_flight.remove(&addr);
+            self.names.insert(addr, name);
+        }
+        if let Some(name) = self.names.get(&addr) {
+            return Some(name.clone().unwrap_or_else(|| "(no PTR)".into()));
+        }
+        let since = match self.hovered {
+            Some((hovered, since)) if hovered == addr => since,
+            _ => {
+                self.hovered = Some((addr, Instant::now()));
+                // come back to look it up if the cursor stays still
+                ctx.request_repaint_after(DEBOUNCE);
+                return None;
+            }
+        };
+        if since.elapsed() < DEBOUNCE {
+            ctx.request_repaint_after(DEBOUNCE - since.elapsed());
+        } else if self.in_flight.insert(addr) {
+            // the resolver blocks, so it gets a thread from the blocking pool
+            let tx = self.tx.clone();
+            let ctx = ctx.clone();
+            tokio::task::spawn_blocking(move || {
+                let name = dns_lookup::lookup_addr(&addr.into()).ok();
+                let _ = tx.send((addr, name));
+                ctx.request_repaint();
+            });
+        }
+        None
+    }
+}
@@ -1,11 +1,24 @@
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
-    @location(1) texture_coords: vec2<f32>
+    @location(1) texture_coords: vec2<f32>,
+    // where the block's texels start in its atlas page
+    @location(2) @interpolate(flat) block_offset: vec2<u32>
 }
 
-struct PanZoomUniform {
+struct ViewUniform {
     pan: vec2<f32>,
-    zoom: vec2<f32>
+    zoom: vec2<f32>,
+    palette: u32,
+    binary: u32,
+    hide_responders: u32,
+    hide_timeouts: u32,
+    grid_prefix: u32,
+    density: u32,
+    diff: u32,
+    cell_size: f32,
+    brightness: f32,
+    gamma: f32,
+    max_latency: f32
 }
 
 struct BlockVertexOutput {
@@ -14,29 +27,38 @@ struct BlockVertexOutput {
 }
 
 struct Instance {
-    @location(0) address: u32,
-    @location(1) texel: u32
+    @location(0) packed: u32
+}
+
+// every block on a page is drawn at once, with one of these for each
+struct BlockInstance {
+    @location(1) offset: vec2<u32>,
+    @location(2) index: u32
 }
 
 @group(0) @binding(0)
 var<uniform> bits_per_block: u32;
 
+// only used when rendering into the blocks
 @group(1) @binding(0)
-var<uniform> pan_zoom: PanZoomUniform;
+var<uniform> block_index: u32;
 
 @group(2) @binding(0)
-var<uniform> block_index: u32;
+var<uniform> view: ViewUniform;
 
 @group(3) @binding(0)
 var texture: texture_2d<u32>;
 
+@group(3) @binding(1)
+var density: texture_2d<u32>;
+
 fn total_width() -> u32 {return 1u << 16u;}
 fn block_width() -> u32 {return 1u << bits_per_block;}
 fn block_bits() -> u32 {return 16u - bits_per_block;}
 
 @vertex
-fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
-    let coords_u = addr_to_coords(block_index, block_bits());
+fn vs_main(block: BlockInstance, @builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let coords_u = addr_to_coords(block.index, block_bits());
     let coords = rescale_coords(coords_u, f32(1u << block_bits()));
 
     var vertex = vertex_from_index(vertex_index);
@@ -47,30 +69,172 @@ fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
     var out: VertexOutput;
     out.clip_position = vec4<f32>(vertex, 1., 1.);
     out.texture_coords = uv_from_index(vertex_index) * f32(block_width());
+    out.block_offset = block.offset;
     return out;
 }
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texels_per_pixel = fwidth(in.texture_coords);
     let texture_coords = vec2<i32>(in.texture_coords);
-    let color = textureLoad(texture, texture_coords, 0).x;
+    // once each /24 is only a few pixels wide, show the fraction of it that responded instead
+    if view.density != 0u && view.diff == 0u && texels_per_pixel.x > 4. {
+        let fraction = block_density(in.block_offset, texture_coords / 16);
+        if fraction == 0u {
+            return vec4<f32>(0.);
+        }
+        return adjust(vec4<f32>(palette_color(f32(fraction - 1u) / 253.), 1.));
+    }
+    let color = load_texel(in.block_offset, in.texture_coords, max(texels_per_pixel.x, texels_per_pixel.y));
+    // no result
     if color == 0u {
         return vec4<f32>(0.);
     }
+    if view.diff != 0u {
+        return adjust(diff_color(color));
+    }
+    if (color == 255u && view.hide_timeouts != 0u) || (color != 255u && view.hide_responders != 0u) {
+        discard;
+    }
+    // in binary mode only whether the address responded matters
+    if view.binary != 0u {
+        if color == 255u {
+            return adjust(vec4<f32>(0.3, 0.02, 0.02, 1.));
+        }
+        return adjust(vec4<f32>(0.1, 0.8, 0.2, 1.));
+    }
+    // timeout
     if color == 255u {
-        return vec4<f32>(0.01);
+        return adjust(vec4<f32>(0.01));
+    }
+    // see `latency_to_color` for a max of 0
+    let t = select(1., clamp(texel_latency(color) / view.max_latency, 0., 1.), view.max_latency > 0.);
+    return adjust(vec4<f32>(palette_color(t), 1.));
+}
+
+// the ping time in seconds of a response, which is stored as 1 to 254 on a log scale from 1ms to 10s.
+// see `texel_to_latency`
+fn texel_latency(color: u32) -> f32 {
+    return 0.001 * (exp(f32(color - 1u) / 253. * log(10001.)) - 1.);
+}
+
+// apply the brightness and gamma settings, leaving the alpha alone
+fn adjust(color: vec4<f32>) -> vec4<f32> {
+    let rgb = pow(clamp(color.rgb * view.brightness, vec3<f32>(0.), vec3<f32>(1.)), vec3<f32>(1. / view.gamma));
+    return vec4<f32>(rgb, color.a);
+}
+
+// Load the texel under the pixel. If addresses are drawn bigger than they are, look around the pixel
+// for a texel that's more interesting to show, so lone responders don't disappear when zoomed out.
+fn load_texel(offset: vec2<u32>, coords: vec2<f32>, texels_per_pixel: f32) -> u32 {
+    // once several texels fall in each pixel, read from the mip level where they're about a texel
+    // each. every mip texel keeps the most interesting one below it, see mip.wgsl
+    let level = u32(clamp(floor(log2(max(texels_per_pixel, 1.))), 0., f32(bits_per_block)));
+    let level_scale = f32(1u << level);
+    let level_coords = coords / level_scale;
+    let radius = (view.cell_size - 1.) / 2. * texels_per_pixel / level_scale;
+    if radius < 0.5 {
+        return block_texel(offset, vec2<i32>(level_coords), level);
+    }
+    // take at most 9x9 samples, so the cost doesn't keep growing as the view zooms out
+    let steps = i32(min(ceil(radius), 4.));
+    let stride = radius / f32(steps);
+    var best = 0u;
+    for (var i = -steps; i <= steps; i += 1) {
+        for (var j = -steps; j <= steps; j += 1) {
+            let sample_offset = vec2<f32>(f32(i), f32(j)) * stride;
+            let texel = block_texel(offset, vec2<i32>(level_coords + sample_offset), level);
+            if texel_rank(texel) > texel_rank(best) {
+                best = texel;
+            }
+        }
     }
-    return vec4<f32>(
-        f32(color) / 255.,
-        f32(255u - color) / 255.,
-        f32(255u - color) / 255.,
-        1.
-    );
+    return best;
+}
+
+// load a texel at a mip level of the block starting at `offset` in the atlas page, staying inside
+// the block at its edges
+fn block_texel(offset: vec2<u32>, coords: vec2<i32>, level: u32) -> u32 {
+    let clamped = clamp(coords, vec2<i32>(0), vec2<i32>(i32(block_width() >> level) - 1));
+    return textureLoad(texture, vec2<i32>(offset >> vec2<u32>(level)) + clamped, i32(level)).x;
+}
+
+// the same as `block_texel`, but for the block's part of the density page
+fn block_density(offset: vec2<u32>, coords: vec2<i32>) -> u32 {
+    let clamped = clamp(coords, vec2<i32>(0), vec2<i32>(i32(block_width() / 16u) - 1));
+    return textureLoad(density, vec2<i32>(offset / 16u) + clamped, 0).x;
+}
+
+// responders beat timeouts, which beat addresses without a result
+fn texel_rank(texel: u32) -> u32 {
+    if texel == 0u {
+        return 0u;
+    }
+    if texel == 255u {
+        return 1u;
+    }
+    return texel + 1u;
+}
+
+// see `diff_texel` for how the changes are encoded
+fn diff_color(color: u32) -> vec4<f32> {
+    switch color {
+        // down both times
+        case 1u: {return vec4<f32>(0.01);}
+        // went down
+        case 2u: {return vec4<f32>(1., 0., 1., 1.);}
+        // came up
+        case 3u: {return vec4<f32>(0., 1., 0., 1.);}
+        default: {
+            let delta = (f32(color) - 129.) / 125.;
+            if delta > 0. {
+                return vec4<f32>(mix(vec3<f32>(0.05), vec3<f32>(1., 0.4, 0.), delta), 1.);
+            }
+            return vec4<f32>(mix(vec3<f32>(0.05), vec3<f32>(0., 0.4, 1.), -delta), 1.);
+        }
+    }
+}
+
+fn palette_color(t: f32) -> vec3<f32> {
+    switch view.palette {
+        case 1u: {return srgb_to_linear(viridis(t));}
+        case 2u: {return srgb_to_linear(magma(t));}
+        case 3u: {return vec3<f32>(t);}
+        default: {return vec3<f32>(t, 1. - t, 1. - t);}
+    }
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    return pow(clamp(color, vec3<f32>(0.), vec3<f32>(1.)), vec3<f32>(2.2));
+}
+
+// polynomial fits of the matplotlib colormaps, which output srgb colors
+fn viridis(t: f32) -> vec3<f32> {
+    let c0 = vec3<f32>(0.2777273272234177, 0.005407344544966578, 0.3340998053353061);
+    let c1 = vec3<f32>(0.1050930431085774, 1.404613529898575, 1.384590162594685);
+    let c2 = vec3<f32>(-0.3308618287255563, 0.214847559468213, 0.09509516302823659);
+    let c3 = vec3<f32>(-4.634230498983486, -5.799100973351585, -19.33244095627987);
+    let c4 = vec3<f32>(6.228269936347081, 14.17993336680509, 56.69055260068105);
+    let c5 = vec3<f32>(4.776384997670288, -13.74514537774601, -65.35303263337234);
+    let c6 = vec3<f32>(-5.435455855934631, 4.645852612178535, 26.3124352495832);
+    return c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6)))));
+}
+
+fn magma(t: f32) -> vec3<f32> {
+    let c0 = vec3<f32>(-0.002136485053939582, -0.000749655052795221, -0.005386127855323933);
+    let c1 = vec3<f32>(0.2516605407371642, 0.6775232436837668, 2.494026599312351);
+    let c2 = vec3<f32>(8.353717279216625, -3.577719514958484, 0.3144679030132573);
+    let c3 = vec3<f32>(-27.66873308576866, 14.26473078096533, -13.64921318813922);
+    let c4 = vec3<f32>(52.17613981234068, -27.94360607168351, 12.94416944238394);
+    let c5 = vec3<f32>(-50.76852536473588, 29.04658282127291, 4.23415299384598);
+    let c6 = vec3<f32>(18.65570506591883, -11.48977351997711, -5.601961508734096);
+    return c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6)))));
 }
 
 @vertex
 fn vs_block(instance: Instance, @builtin(vertex_index) vertex_index: u32) -> BlockVertexOutput {
-    let coords_u = addr_to_coords(instance.address, 16u) % block_width();
+    let address = (block_index << (2u * bits_per_block)) | (instance.packed & 0xffffffu);
+    let coords_u = addr_to_coords(address, 16u) % block_width();
     let coords = rescale_coords(coords_u, f32(block_width()));
 
     var vertex = vertex_from_index(vertex_index);
@@ -79,7 +243,7 @@ fn vs_block(instance: Instance, @builtin(vertex_index) vertex_index: u32) -> Blo
 
     var out: BlockVertexOutput;
     out.clip_position = vec4<f32>(vertex, 1., 1.);
-    out.texel = instance.texel;
+    out.texel = instance.packed >> 24u;
     return out;
 }
 
@@ -89,13 +253,15 @@ fn fs_block(in: BlockVertexOutput) -> @location(0) u32 {
 }
 
 fn apply_pan_zoom(vertex: vec2<f32>) -> vec2<f32> {
-    return (vertex + pan_zoom.pan) * pan_zoom.zoom;
+    return (vertex + view.pan) * view.zoom;
 }
 
 fn rescale_coords(coords: vec2<u32>, scaler: f32) -> vec2<f32> {
     return (vec2<f32>(coords) + 0.5) / scaler * 2. - 1.;
 }
 
+// the position of `d` on a hilbert curve of order `bits`, so each coordinate has `bits` bits and
+// `d` has twice as many. see `addr_to_coords` in ping_map.rs
 fn addr_to_coords(d: u32, bits: u32) -> vec2<u32> {
     var out = vec2<u32>(0u, 0u);
     var d = d;
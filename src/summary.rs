@@ -0,0 +1,175 @@
+use float_ord::FloatOrd;
+use std::{cmp::Reverse, collections::BinaryHeap, fmt::Write, io, net::Ipv4Addr, path::Path};
+
+use crate::ping_file::MappedFile;
+
+/// Statistics over the results in a ping file
+pub struct Summary {
+    pub num_addrs: u64,
+    pub num_results: u64,
+    pub num_responders: u64,
+    pub num_timeouts: u64,
+    pub min_rtt: Option<f32>,
+    pub mean_rtt: Option<f32>,
+    pub max_rtt: Option<f32>,
+    /// The slowest responders and their ping times, slowest first
+    pub slowest: Vec<(Ipv4Addr, f32)>,
+}
+impl Summary {
+    /// Read every result in the file at the given path, keeping track of the `num_slowest` slowest
+    /// responders
+    pub async fn from_file(path: impl AsRef<Path>, num_slowest: usize) -> io::Result<Self> {
+        let file = MappedFile::open(path).await?;
+        let header = file.header();
+        let mut summary = Self {
+            num_addrs: header.addresses().count() as u64,
+            num_results: 0,
+            num_responders: 0,
+            num_timeouts: 0,
+            min_rtt: None,
+            mean_rtt: None,
+            max_rtt: None,
+            slowest: vec![],
+        };
+        let mut total_rtt = 0.;
+        let mut slowest = Slowest::new(num_slowest);
+        let num_results = file.num_results() as usize;
+        for (offset, addr) in header.addresses_with_offsets().take(num_results) {
+            let rtt = file.get(offset).unwrap();
+            summary.num_results += 1;
+            if rtt < 0. {
+                summary.num_timeouts += 1;
+                continue;
+            }
+            summary.num_responders += 1;
+            total_rtt += rtt as f64;
+            summary.min_rtt = Some(summary.min_rtt.map_or(rtt, |min| min.min(rtt)));
+            summary.max_rtt = Some(summary.max_rtt.map_or(rtt, |max| max.max(rtt)));
+            slowest.insert(addr, rtt);
+        }
+        if summary.num_responders > 0 {
+            summary.mean_rtt = Some((total_rtt / summary.num_responders as f64) as f32);
+        }
+        summary.slowest = slowest.to_vec();
+        Ok(summary)
+    }
+    pub fn to_text(&self) -> String {
+        let ms =
+            |rtt: Option<f32>| rtt.map_or("-".to_string(), |rtt| format!("{:.2}ms", rtt * 1e3));
+        let mut text = String::new();
+        writeln!(
+            text,
+            "{} of {} addresses pinged",
+            self.num_results, self.num_addrs
+        )
+        .unwrap();
+        writeln!(text, "{} responders", self.num_responders).unwrap();
+        writeln!(text, "{} timeouts", self.num_timeouts).unwrap();
+        writeln!(
+            text,
+            "min/mean/max: {}/{}/{}",
+            ms(self.min_rtt),
+            ms(self.mean_rtt),
+            ms(self.max_rtt)
+        )
+        .unwrap();
+        if !self.slowest.is_empty() {
+            writeln!(text, "slowest responders:").unwrap();
+        }
+        for (addr, rtt) in &self.slowest {
+            writeln!(text, "  {addr:<15} {}", ms(Some(*rtt))).unwrap();
+        }
+        text
+    }
+    pub fn to_json(&self) -> String {
+        let ms = |rtt: Option<f32>| rtt.map(|rtt| rtt * 1e3);
+        let slowest = self
+            .slowest
+            .iter()
+            .map(|(addr, rtt)| serde_json::json!({"ip": addr, "rtt_ms": ms(Some(*rtt))}))
+            .collect::<Vec<_>>();
+        serde_json::json!({
+            "addresses": self.num_addrs,
+            "pinged": self.num_results,
+            "responders": self.num_responders,
+            "timeouts": self.num_timeouts,
+            "min_rtt_ms": ms(self.min_rtt),
+            "mean_rtt_ms": ms(self.mean_rtt),
+            "max_rtt_ms": ms(self.max_rtt),
+            "slowest": slowest,
+        })
+        .to_string()
+    }
+}
+
+/// The slowest responders seen so far, keeping no more than a fixed number of them
+pub struct Slowest {
+    /// A min-heap, so the fastest of them can be swapped out
+    heap: BinaryHeap<Reverse<(FloatOrd<f32>, Ipv4Addr)>>,
+    max_len: usize,
+}
+impl Slowest {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            heap: BinaryHeap::with_capacity(max_len + 1),
+            max_len,
+        }
+    }
+    pub fn insert(&mut self, addr: Ipv4Addr, rtt: f32) {
+        self.heap.push(Reverse((FloatOrd(rtt), addr)));
+        if self.heap.len() > self.max_len {
+            self.heap.pop();
+        }
+    }
+    /// The responders and their ping times, slowest first
+    pub fn to_vec(&self) -> Vec<(Ipv4Addr, f32)> {
+        self.heap
+            .clone()
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((FloatOrd(rtt), addr))| (addr, rtt))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_has_nulls_without_responders() {
+        let summary = Summary {
+            num_addrs: 254,
+            num_results: 10,
+            num_responders: 0,
+            num_timeouts: 10,
+            min_rtt: None,
+            mean_rtt: None,
+            max_rtt: None,
+            slowest: vec![],
+        };
+        let json = serde_json::from_str::<serde_json::Value>(&summary.to_json()).unwrap();
+        assert_eq!(json["addresses"], 254);
+        assert_eq!(json["timeouts"], 10);
+        assert!(json["min_rtt_ms"].is_null() && json["max_rtt_ms"].is_null());
+        assert_eq!(json["slowest"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn json_lists_the_slowest_responders() {
+        let summary = Summary {
+            num_addrs: 2,
+            num_results: 2,
+            num_responders: 2,
+            num_timeouts: 0,
+            min_rtt: Some(0.001),
+            mean_rtt: Some(0.0015),
+            max_rtt: Some(0.002),
+            slowest: vec![(Ipv4Addr::new(10, 0, 0, 2), 0.002)],
+        };
+        let json = serde_json::from_str::<serde_json::Value>(&summary.to_json()).unwrap();
+        assert_eq!(json["slowest"][0]["ip"], "10.0.0.2");
+        let rtt_ms = json["slowest"][0]["rtt_ms"].as_f64().unwrap();
+        assert!((rtt_ms - 2.).abs() < 1e-4);
+    }
+}
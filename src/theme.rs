@@ -0,0 +1,45 @@
+use std::{io, path::PathBuf};
+
+use crate::config;
+
+/// The look of the menus and panels around the map, which is remembered between runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    Dark,
+    Light,
+}
+impl Theme {
+    pub const ALL: [Self; 2] = [Self::Dark, Self::Light];
+    pub fn name(self) -> &'static str {
+        match self {
+            Theme::Dark => "Dark",
+            Theme::Light => "Light",
+        }
+    }
+    pub fn visuals(self) -> egui::Visuals {
+        match self {
+            Theme::Dark => egui::Visuals::dark(),
+            Theme::Light => egui::Visuals::light(),
+        }
+    }
+    /// Load the theme picked last time, or the dark one if there wasn't one
+    pub fn load() -> Self {
+        let contents = Self::path().and_then(|path| std::fs::read_to_string(path).ok());
+        match contents.as_deref().map(str::trim) {
+            Some("light") => Theme::Light,
+            _ => Theme::Dark,
+        }
+    }
+    pub fn save(self) -> io::Result<()> {
+        let path = Self::path().ok_or(io::ErrorKind::NotFound)?;
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        let contents = match self {
+            Theme::Dark => "dark\n",
+            Theme::Light => "light\n",
+        };
+        std::fs::write(path, contents)
+    }
+    fn path() -> Option<PathBuf> {
+        Some(config::dir()?.join("theme"))
+    }
+}
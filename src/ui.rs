@@ -3,26 +3,72 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use winit::{event::WindowEvent, event_loop::ControlFlow};
+use winit::{
+    event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event_loop::ControlFlow,
+    window::{Fullscreen, Icon},
+};
 
-use crate::gpu::GpuState;
+use crate::color;
+use crate::gpu::{self, Background, GpuState};
+use crate::metadata::Metadata;
+use crate::ping_file::MappedFile;
 use crate::ping_map;
+use crate::reverse_dns::ReverseDns;
+use crate::theme::Theme;
+use crate::wgpu_ext::PassTimer;
+use crate::window_geometry::WindowGeometry;
 
 const INITIAL_WIDTH: u32 = 1920;
 const INITIAL_HEIGHT: u32 = 1080;
+const TITLE: &str = "Pinger";
+const PERF_OVERLAY_KEY: egui::Key = egui::Key::F3;
+/// How much of each new frame time goes into the smoothed one
+const FRAME_TIME_SMOOTHING: f32 = 0.05;
+/// An RGBA PNG
+const ICON: &[u8] = include_bytes!("icon.png");
+
+pub async fn main(args: Args) {
+    // Check that the files can be compared before opening a window
+    let diff = match args.diff {
+        Some(ref paths) => match open_diff(&paths[0], &paths[1]).await {
+            Ok(files) => Some(files),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
 
-pub async fn main() {
     let event_loop = winit::event_loop::EventLoop::new();
-    let window = winit::window::WindowBuilder::new()
-        .with_title("Pinger")
+    let mut window_builder = winit::window::WindowBuilder::new()
+        .with_title(TITLE)
+        .with_window_icon(Some(load_icon()))
         .with_inner_size(winit::dpi::PhysicalSize {
             width: INITIAL_WIDTH,
             height: INITIAL_HEIGHT,
-        })
-        .build(&event_loop)
-        .unwrap();
+        });
+    // Open where the window was last time, unless that's no longer on any monitor
+    if let Some(geometry) = WindowGeometry::load() {
+        if geometry.is_visible(event_loop.available_monitors()) {
+            window_builder = geometry.apply(window_builder);
+        }
+    }
+    let window = window_builder.build(&event_loop).unwrap();
 
-    let mut gpu = GpuState::new(&window).await;
+    let mut gpu = match GpuState::new(&window, &args.gpu).await {
+        Ok(gpu) => gpu,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            eprintln!(
+                "Make sure up to date graphics drivers are installed. On machines without a GPU, \
+                 a software renderer such as Mesa's llvmpipe can be used with `--backend gl` or \
+                 `--backend vulkan`."
+            );
+            std::process::exit(1);
+        }
+    };
 
     let mut egui_platform = egui_winit::State::new(&window);
     egui_platform.set_pixels_per_point(window.scale_factor() as f32);
@@ -34,7 +80,16 @@ pub async fn main() {
     );
     let egui_ctx = egui::Context::default();
 
-    let mut ui_state = UiState::new(&gpu, &mut egui_renderer);
+    let mut ui_state = UiState::new(&gpu, &mut egui_renderer, args.max_points);
+    let mut frame_timer = gpu
+        .timestamp_period
+        .map(|period| PassTimer::new(&gpu.device, period, "Rendering the frame"));
+    ui_state.background = args.background;
+    if let (Some(paths), Some((before, after))) = (args.diff, diff) {
+        ui_state.ping_map.open_diff(before, after);
+        ui_state.comparing = Some((paths[0].clone(), paths[1].clone()));
+    }
+    let mut title = TITLE.to_string();
 
     event_loop.run(move |event, _, control_flow| match event {
         winit::event::Event::WindowEvent { event, .. } => {
@@ -54,7 +109,39 @@ pub async fn main() {
                     gpu.resize(*new_inner_size);
                     window.request_redraw();
                 }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(key),
+                            ..
+                        },
+                    ..
+                } => {
+                    // the resize that going in and out of full-screen causes is handled like any
+                    // other, and the map keeps its pan and zoom
+                    match key {
+                        VirtualKeyCode::F11 if window.fullscreen().is_none() => {
+                            window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+                        }
+                        VirtualKeyCode::F11 | VirtualKeyCode::Escape => {
+                            window.set_fullscreen(None);
+                        }
+                        _ => {}
+                    }
+                }
                 WindowEvent::CloseRequested => {
+                    // the next run shouldn't open at the full-screen size, so keep the geometry
+                    // that was saved before
+                    let geometry = match window.fullscreen() {
+                        Some(_) => None,
+                        None => WindowGeometry::from_window(&window),
+                    };
+                    if let Some(geometry) = geometry {
+                        if let Err(e) = geometry.save() {
+                            tracing::warn!("Failed to save the window geometry: {e}");
+                        }
+                    }
                     *control_flow = ControlFlow::Exit;
                 }
                 _ => {}
@@ -63,13 +150,35 @@ pub async fn main() {
         winit::event::Event::RedrawRequested(..) => {
             let span = tracing::trace_span!("Render Frame");
             let _span = span.enter();
-            let Ok((surface, view)) = gpu.get_surface_texture() else {
-                return
+            let (surface, view) = match gpu.get_surface_texture() {
+                Ok(texture) => texture,
+                // the surface no longer matches the window, e.g. after moving to another monitor,
+                // so reconfigure it and try again next frame
+                Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                    gpu.resize(window.inner_size());
+                    window.request_redraw();
+                    return;
+                }
+                Err(wgpu::SurfaceError::OutOfMemory) => {
+                    eprintln!("Error: the GPU ran out of memory");
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+                // the frame took too long to be ready, so skip it
+                Err(wgpu::SurfaceError::Timeout) => {
+                    window.request_redraw();
+                    return;
+                }
             };
 
             let egui_input = egui_platform.take_egui_input(&window);
             egui_ctx.begin_frame(egui_input);
             ui_state.run(&egui_ctx);
+            let new_title = ui_state.title();
+            if new_title != title {
+                window.set_title(&new_title);
+                title = new_title;
+            }
             let egui_output = egui_ctx.end_frame();
             egui_platform.handle_platform_output(&window, &egui_ctx, egui_output.platform_output);
 
@@ -86,12 +195,21 @@ pub async fn main() {
             for (texture_id, image_delta) in egui_output.textures_delta.set {
                 egui_renderer.update_texture(&gpu.device, &gpu.queue, texture_id, &image_delta);
             }
+            if let Some(ref mut timer) = frame_timer {
+                timer.begin(&mut encoder);
+            }
             egui_renderer.render(
-                &mut gpu.create_render_pass(&mut encoder, &view),
+                &mut gpu.create_render_pass(&mut encoder, &view, ui_state.background),
                 &egui_primitives[..],
                 &screen_descriptor,
             );
+            if let Some(ref mut timer) = frame_timer {
+                timer.end(&mut encoder);
+            }
             gpu.queue.submit(iter::once(encoder.finish()));
+            if let Some(ref mut timer) = frame_timer {
+                timer.read();
+            }
             surface.present();
             for texture_id in egui_output.textures_delta.free {
                 egui_renderer.free_texture(&texture_id);
@@ -107,16 +225,49 @@ pub async fn main() {
 pub struct UiState {
     file_open_dialog: FileDialog,
     ping_map: ping_map::Widget,
+    /// The files being compared, if the map is showing a diff
+    comparing: Option<(PathBuf, PathBuf)>,
+    /// When the open file was scanned and with what settings, if it has a sidecar
+    metadata: Option<Metadata>,
+    /// An error to show until it's dismissed
+    error: Option<String>,
+    background: Background,
+    theme: Theme,
+    reverse_dns: ReverseDns,
+    /// Show the frame rate and how much GPU memory the map is using
+    perf_overlay: bool,
+    /// The time between frames in seconds, smoothed out so the overlay is readable
+    frame_time: f32,
+    /// Where to export the responders in the selected region to, while it's being picked
+    export_dialog: Option<egui_file::FileDialog>,
+    /// How the last export of the selected region went
+    export_message: Option<String>,
 }
 impl UiState {
-    pub fn new(gpu: &GpuState, egui_renderer: &mut egui_wgpu::Renderer) -> Self {
-        let ping_map = ping_map::Widget::new(gpu, egui_renderer);
+    pub fn new(
+        gpu: &GpuState,
+        egui_renderer: &mut egui_wgpu::Renderer,
+        max_points: Option<u64>,
+    ) -> Self {
+        let ping_map = ping_map::Widget::new(gpu, egui_renderer, max_points);
         Self {
             file_open_dialog: FileDialog::new(),
             ping_map,
+            comparing: None,
+            metadata: None,
+            error: None,
+            background: Background::Black,
+            theme: Theme::load(),
+            reverse_dns: ReverseDns::default(),
+            perf_overlay: false,
+            frame_time: 1. / 60.,
+            export_dialog: None,
+            export_message: None,
         }
     }
     pub fn run(&mut self, ctx: &egui::Context) {
+        // the map is drawn by its own shader, so this only changes what's around it
+        ctx.set_visuals(self.theme.visuals());
         egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.menu_button("File", |ui| {
@@ -124,15 +275,81 @@ impl UiState {
                         ui.close_menu();
                         self.file_open_dialog.open();
                     }
+                    let open_path = match self.comparing {
+                        Some(_) => None,
+                        None => self.file_open_dialog.path.clone(),
+                    };
+                    let replay = ui.add_enabled(open_path.is_some(), egui::Button::new("Replay"));
+                    if replay
+                        .on_hover_text("Watch the open file fill in, in the order it was scanned")
+                        .clicked()
+                    {
+                        ui.close_menu();
+                        self.ping_map.replay_file(open_path.unwrap());
+                    }
+                    if ui.button("Close").clicked() {
+                        ui.close_menu();
+                        self.ping_map.close();
+                        self.file_open_dialog.path = None;
+                        self.comparing = None;
+                        self.metadata = None;
+                    }
                 });
-                if let Some(ref path) = self.file_open_dialog.path {
+                ui.menu_button("View", |ui| self.view_menu(ui));
+                if let Some(progress) = self.ping_map.load_progress() {
+                    ui.add(
+                        egui::ProgressBar::new(progress)
+                            .desired_width(200.)
+                            .show_percentage(),
+                    );
+                    if ui.button("Cancel").clicked() {
+                        self.ping_map.cancel_load();
+                    }
+                }
+                if let Some((ref before, ref after)) = self.comparing {
                     ui.label(format!(
+                        "Comparing: {:?} to {:?}",
+                        before.file_name().unwrap().to_str().unwrap(),
+                        after.file_name().unwrap().to_str().unwrap()
+                    ));
+                } else if let Some(ref path) = self.file_open_dialog.path {
+                    let label = ui.label(format!(
                         "Current File: {:?}",
                         path.file_name().unwrap().to_str().unwrap()
                     ));
+                    if let Some(ref metadata) = self.metadata {
+                        label.on_hover_text(metadata.to_text());
+                    }
                 }
             })
         });
+        egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label(format!("Zoom: {:.0}%", self.ping_map.zoom() * 100.));
+                if let Some(addr) = self.ping_map.hovered() {
+                    ui.separator();
+                    if let Some([r, g, b, a]) = self.ping_map.result_color(addr) {
+                        let (rect, _) =
+                            ui.allocate_exact_size(egui::vec2(12., 12.), egui::Sense::hover());
+                        let color = egui::Color32::from_rgba_unmultiplied(r, g, b, a);
+                        ui.painter().rect_filled(rect, 2., color);
+                    }
+                    ui.label(format!("{addr}: {}", self.ping_map.describe_result(addr)));
+                    if self.reverse_dns.enabled && self.ping_map.responded(addr) {
+                        ui.separator();
+                        match self.reverse_dns.hostname(addr, ctx) {
+                            Some(hostname) => ui.label(hostname),
+                            None => ui.weak("looking up hostname..."),
+                        };
+                    }
+                }
+            })
+        });
+        if self.ping_map.is_replaying() {
+            egui::TopBottomPanel::bottom("replay_controls").show(ctx, |ui| {
+                ui.horizontal(|ui| self.ping_map.replay_controls(ui));
+            });
+        }
         egui::CentralPanel::default()
             .frame(egui::Frame {
                 inner_margin: egui::Margin::same(0.),
@@ -146,9 +363,200 @@ impl UiState {
                 self.ping_map.show(ui);
             });
         if self.file_open_dialog.show(ctx).just_selected {
-            self.ping_map
-                .open_file(self.file_open_dialog.path.as_ref().unwrap());
+            let path = self.file_open_dialog.path.as_ref().unwrap();
+            self.comparing = None;
+            self.metadata = Metadata::load(path).unwrap_or_else(|e| {
+                tracing::warn!("Failed to read the metadata for {}: {e}", path.display());
+                None
+            });
+            self.ping_map.open_file(path);
+        }
+        if let Some(e) = self.ping_map.take_load_error() {
+            self.error = Some(match self.file_open_dialog.path.take() {
+                Some(path) => format!("{}: {e}", path.display()),
+                None => e.to_string(),
+            });
+            self.metadata = None;
         }
+        self.error_window(ctx);
+        self.selection_window(ctx);
+        self.perf_overlay(ctx);
+    }
+    fn perf_overlay(&mut self, ctx: &egui::Context) {
+        if ctx.input(|i| i.key_pressed(PERF_OVERLAY_KEY)) {
+            self.perf_overlay = !self.perf_overlay;
+        }
+        let dt = ctx.input(|i| i.unstable_dt);
+        self.frame_time += (dt - self.frame_time) * FRAME_TIME_SMOOTHING;
+        if !self.perf_overlay {
+            return;
+        }
+        egui::Area::new("perf_overlay")
+            .anchor(egui::Align2::LEFT_TOP, [8., 32.])
+            .interactable(false)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.monospace(format!(
+                        "{:.0} fps ({:.1} ms)",
+                        1. / self.frame_time,
+                        self.frame_time * 1000.
+                    ));
+                    let mib = self.ping_map.gpu_memory() as f64 / (1 << 20) as f64;
+                    ui.monospace(format!("{mib:.1} MiB of GPU memory"));
+                });
+            });
+    }
+    /// The window title, which names what's open
+    fn title(&self) -> String {
+        let file_name = |path: &Path| path.file_name().unwrap().to_string_lossy().into_owned();
+        if let Some((ref before, ref after)) = self.comparing {
+            format!("{TITLE} — {} vs {}", file_name(before), file_name(after))
+        } else if let Some(ref path) = self.file_open_dialog.path {
+            format!("{TITLE} — {}", file_name(path))
+        } else {
+            TITLE.into()
+        }
+    }
+    fn error_window(&mut self, ctx: &egui::Context) {
+        let Some(ref error) = self.error else {
+            return;
+        };
+        let mut dismissed = false;
+        egui::Window::new("Failed to Open File")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+            .show(ctx, |ui| {
+                ui.label(error);
+                dismissed = ui.button("OK").clicked();
+            });
+        if dismissed {
+            self.error = None;
+        }
+    }
+    fn selection_window(&mut self, ctx: &egui::Context) {
+        let Some(stats) = self.ping_map.selection_stats() else {
+            self.export_message = None;
+            return;
+        };
+        let mut cleared = false;
+        let mut export = false;
+        egui::Window::new("Selection")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::RIGHT_TOP, [-8., 8.])
+            .show(ctx, |ui| {
+                egui::Grid::new("selection_stats").show(ui, |ui| {
+                    ui.label("Addresses");
+                    ui.label(stats.num_addrs.to_string());
+                    ui.end_row();
+                    ui.label("Results");
+                    ui.label(stats.num_results.to_string());
+                    ui.end_row();
+                    ui.label("Responders");
+                    ui.label(stats.num_responders.to_string());
+                    ui.end_row();
+                    ui.label("Up");
+                    ui.label(match stats.num_results {
+                        0 => "-".into(),
+                        n => format!("{:.1}%", stats.num_responders as f64 / n as f64 * 100.),
+                    });
+                    ui.end_row();
+                    ui.label("Mean Ping");
+                    ui.label(match stats.mean_rtt {
+                        Some(rtt) => format!("{:.1} ms", rtt * 1000.),
+                        None => "-".into(),
+                    });
+                    ui.end_row();
+                });
+                if let Some(ref message) = self.export_message {
+                    ui.label(message);
+                }
+                ui.horizontal(|ui| {
+                    export = ui
+                        .add_enabled(
+                            stats.num_responders > 0,
+                            egui::Button::new("Export Responders..."),
+                        )
+                        .on_disabled_hover_text("There are no responders in the selection")
+                        .clicked();
+                    cleared = ui.button("Clear").clicked();
+                });
+            });
+        if export {
+            let mut dialog = egui_file::FileDialog::save_file(None);
+            dialog.open();
+            self.export_dialog = Some(dialog);
+        }
+        if cleared {
+            self.ping_map.clear_selection();
+            self.export_message = None;
+        }
+        if let Some(ref mut dialog) = self.export_dialog {
+            if dialog.show(ctx).selected() {
+                let mut path = dialog.path().unwrap();
+                if path.extension().is_none() {
+                    path.set_extension("csv");
+                }
+                self.export_message = Some(match self.ping_map.export_selection(&path) {
+                    Ok(n) => format!("Exported {n} responders to {}", path.display()),
+                    Err(e) => format!("Failed to export to {}: {e}", path.display()),
+                });
+            }
+            if !dialog.visible() {
+                self.export_dialog = None;
+            }
+        }
+    }
+    fn view_menu(&mut self, ui: &mut egui::Ui) {
+        let ping_map = &mut self.ping_map;
+        ui.menu_button("Palette", |ui| {
+            for p in color::Palette::ALL {
+                ui.radio_value(&mut ping_map.palette, p, p.name());
+            }
+        });
+        ui.menu_button("Background", |ui| {
+            for b in Background::ALL {
+                ui.radio_value(&mut self.background, b, b.name());
+            }
+        });
+        ui.menu_button("Theme", |ui| {
+            for t in Theme::ALL {
+                if ui.radio_value(&mut self.theme, t, t.name()).changed() {
+                    if let Err(e) = t.save() {
+                        tracing::warn!("Failed to save the theme: {e}");
+                    }
+                }
+            }
+        });
+        ui.menu_button("Gridlines", |ui| {
+            ui.radio_value(&mut ping_map.grid_prefix, None, "Off");
+            // only even prefixes make squares on the map
+            for p in (8..=28).step_by(4) {
+                ui.radio_value(&mut ping_map.grid_prefix, Some(p), format!("/{p}"));
+            }
+        });
+        ui.checkbox(&mut ping_map.binary, "Up/Down Only");
+        ui.checkbox(&mut ping_map.hide_responders, "Hide Responders");
+        ui.checkbox(&mut ping_map.hide_timeouts, "Hide Timeouts");
+        ui.checkbox(&mut ping_map.density, "/24 Density When Zoomed Out");
+        ui.checkbox(&mut ping_map.minimap, "Minimap");
+        ui.checkbox(&mut self.reverse_dns.enabled, "Look Up Hostnames on Hover");
+        ui.checkbox(&mut self.perf_overlay, "Performance Overlay (F3)");
+        ui.add(egui::Slider::new(&mut ping_map.cell_size, 1.0..=8.0).text("Cell Size"));
+        ui.add(egui::Slider::new(&mut ping_map.brightness, 0.25..=4.0).text("Brightness"));
+        ui.add(egui::Slider::new(&mut ping_map.gamma, 0.25..=4.0).text("Gamma"));
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::Slider::new(&mut ping_map.max_latency, 0.001..=10.0)
+                    .logarithmic(true)
+                    .suffix(" s")
+                    .text("Max Latency"),
+            );
+            if ui.button("Auto-fit").clicked() {
+                ping_map.auto_fit_latency();
+            }
+        });
     }
 }
 
@@ -159,7 +567,10 @@ struct FileDialog {
 }
 impl FileDialog {
     fn new() -> Self {
-        let filter = |path: &Path| path.extension().is_some_and(|s| s == "ping");
+        let filter = |path: &Path| {
+            let name = path.to_string_lossy();
+            name.ends_with(".ping") || name.ends_with(".ping.gz")
+        };
         let dialog = egui_file::FileDialog::open_file(None).filter(Box::new(filter));
         Self {
             dialog,
@@ -179,3 +590,50 @@ impl FileDialog {
         self.dialog.open();
     }
 }
+
+fn load_icon() -> Icon {
+    let mut reader = png::Decoder::new(ICON).read_info().unwrap();
+    let mut rgba = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut rgba).unwrap();
+    rgba.truncate(info.buffer_size());
+    Icon::from_rgba(rgba, info.width, info.height).unwrap()
+}
+
+/// Open two scans of the same range to compare
+async fn open_diff(before: &Path, after: &Path) -> Result<(MappedFile, MappedFile), String> {
+    let before_file = MappedFile::open(before)
+        .await
+        .map_err(|e| format!("{}: {e}", before.display()))?;
+    let after_file = MappedFile::open(after)
+        .await
+        .map_err(|e| format!("{}: {e}", after.display()))?;
+    let (before_header, after_header) = (before_file.header(), after_file.header());
+    if before_header.range != after_header.range
+        || before_header.all_addresses != after_header.all_addresses
+        || before_header.stride != after_header.stride
+    {
+        return Err(format!(
+            "{} and {} are scans of different ranges, so they can't be compared",
+            before.display(),
+            after.display()
+        ));
+    }
+    Ok((before_file, after_file))
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    #[command(flatten)]
+    gpu: gpu::Args,
+    /// Show how the results changed between two scans of the same range
+    #[arg(long, num_args = 2, value_names = ["BEFORE", "AFTER"])]
+    diff: Option<Vec<PathBuf>>,
+    /// The color to show behind the map
+    #[arg(default_value = "black", long)]
+    background: Background,
+    /// Keep at most about this many results in memory besides the map's textures, for machines
+    /// without much memory. Older results are still drawn, but hovering over them no longer
+    /// describes them. Without this, a whole scan of the internet needs around 4 GiB.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_points: Option<u64>,
+}
@@ -0,0 +1,74 @@
+use itertools::Itertools;
+use std::path::PathBuf;
+
+use crate::ping_file::{parse_range_from_path, MappedFile};
+
+pub async fn main(args: Args) {
+    let path = args.file.display();
+    let file = match MappedFile::open(&args.file).await {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Error: {path}: {e}");
+            std::process::exit(1);
+        }
+    };
+    let header = file.header();
+    let mut problems = vec![];
+
+    // A scan that was interrupted mid-write leaves part of a result at the end of the file
+    let result_size = header.precision.size();
+    let partial_len = file.data_len() % result_size;
+    if partial_len != 0 {
+        problems.push(format!(
+            "the last result is cut off after {partial_len} of its {result_size} bytes"
+        ));
+    }
+
+    let num_addrs = header.addresses().count() as u64;
+    if file.num_results() > num_addrs {
+        problems.push(format!(
+            "there are {} results, but the range only has {num_addrs} addresses",
+            file.num_results()
+        ));
+    }
+
+    // Files without a header get their range from the filename, so they always match
+    if file.has_header() {
+        if let Some(mut path_range) = parse_range_from_path(&args.file) {
+            let mut header_range = header.range.clone();
+            header_range.simplify();
+            path_range.simplify();
+            if header_range != path_range {
+                problems.push(format!(
+                    "the range in the header ({}) doesn't match the filename ({})",
+                    header_range.iter().join(", "),
+                    path_range.iter().join(", ")
+                ));
+            }
+        }
+    }
+
+    // Every result should be a ping time or -1 for a timeout
+    let num_invalid = (0..file.num_results())
+        .map(|offset| file.get(offset).unwrap())
+        .filter(|&rtt| rtt != -1. && !(rtt >= 0. && rtt.is_finite()))
+        .count();
+    if num_invalid > 0 {
+        problems.push(format!("{num_invalid} results aren't valid ping times"));
+    }
+
+    if problems.is_empty() {
+        println!("{path}: OK");
+        return;
+    }
+    for problem in problems {
+        eprintln!("{path}: {problem}");
+    }
+    std::process::exit(1);
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// The ping file to check
+    file: PathBuf,
+}
@@ -1,7 +1,18 @@
-use std::{marker::PhantomData, slice::Iter};
+use std::{
+    marker::PhantomData,
+    slice::Iter,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use bytemuck::cast_slice;
-use wgpu::{Buffer, BufferAddress, BufferDescriptor, BufferUsages, Device, Queue};
+use wgpu::{
+    util::StagingBelt, Buffer, BufferAddress, BufferDescriptor, BufferSize, BufferUsages,
+    CommandEncoder, Device, MapMode, QuerySet, QuerySetDescriptor, QueryType,
+};
 
 pub struct BufferVec<T> {
     instance_buffers: Vec<(Buffer, usize)>,
@@ -30,9 +41,16 @@ impl<T> BufferVec<T> {
             0,
         ));
     }
+    /// Append the data, copying it in through the staging belt as part of the encoder's commands.
+    /// Unlike `Queue::write_buffer`, the staging memory is reused once the GPU is done with it.
     #[tracing::instrument(skip_all, name = "Extend Buffers")]
-    pub fn extend(&mut self, device: &Device, queue: &Queue, mut data: &[T])
-    where
+    pub fn extend(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        staging_belt: &mut StagingBelt,
+        mut data: &[T],
+    ) where
         T: bytemuck::Pod,
     {
         if self.instance_buffers.is_empty() {
@@ -43,11 +61,19 @@ impl<T> BufferVec<T> {
             let remaining_slots = self.max_num_slots - *num_occupied;
             let offset = *num_occupied as BufferAddress * Self::DATA_SIZE;
             if data.len() < remaining_slots {
-                queue.write_buffer(buffer, offset, cast_slice(data));
+                write(
+                    device,
+                    encoder,
+                    staging_belt,
+                    buffer,
+                    offset,
+                    cast_slice(data),
+                );
                 *num_occupied += data.len();
                 break;
             }
-            queue.write_buffer(buffer, offset, cast_slice(&data[..remaining_slots]));
+            let bytes = cast_slice(&data[..remaining_slots]);
+            write(device, encoder, staging_belt, buffer, offset, bytes);
             *num_occupied += remaining_slots;
             data = &data[remaining_slots..];
             self.push_new_buffer(device);
@@ -65,7 +91,34 @@ impl<T> BufferVec<T> {
     pub fn len(&self) -> usize {
         self.instance_buffers.len()
     }
+
+    /// The number of items in all of the buffers
+    pub fn num_items(&self) -> usize {
+        self.instance_buffers.iter().map(|(_, n)| n).sum()
+    }
+
+    /// The number of bytes allocated for the buffers, whether or not they're full
+    pub fn allocated_size(&self) -> BufferAddress {
+        self.instance_buffers.len() as BufferAddress * self.max_buffer_size
+    }
+}
+fn write(
+    device: &Device,
+    encoder: &mut CommandEncoder,
+    staging_belt: &mut StagingBelt,
+    buffer: &Buffer,
+    offset: BufferAddress,
+    bytes: &[u8],
+) {
+    // the staging belt can't write nothing
+    let Some(size) = BufferSize::new(bytes.len() as _) else {
+        return;
+    };
+    staging_belt
+        .write_buffer(encoder, buffer, offset, size, device)
+        .copy_from_slice(bytes);
 }
+
 impl<'a, T> IntoIterator for &'a BufferVec<T> {
     type Item = &'a (Buffer, usize);
 
@@ -75,3 +128,94 @@ impl<'a, T> IntoIterator for &'a BufferVec<T> {
         self.iter()
     }
 }
+
+/// Times the commands an encoder records between `begin` and `end` with timestamp queries, and
+/// logs how long the GPU took once the results are read back. A frame is skipped if the last
+/// one's results haven't come back yet.
+pub struct PassTimer {
+    label: &'static str,
+    query_set: QuerySet,
+    resolve_buffer: Buffer,
+    readback_buffer: Arc<Buffer>,
+    /// The number of nanoseconds per timestamp tick
+    period: f32,
+    /// Whether the readback buffer is still waiting to be mapped or read
+    pending: Arc<AtomicBool>,
+    recording: bool,
+}
+impl PassTimer {
+    const SIZE: BufferAddress = 2 * std::mem::size_of::<u64>() as BufferAddress;
+    /// Needs a device with `Features::TIMESTAMP_QUERY`
+    pub fn new(device: &Device, period: f32, label: &'static str) -> Self {
+        let query_set = device.create_query_set(&QuerySetDescriptor {
+            label: Some(label),
+            ty: QueryType::Timestamp,
+            count: 2,
+        });
+        let resolve_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some(label),
+            size: Self::SIZE,
+            usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some(label),
+            size: Self::SIZE,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Self {
+            label,
+            query_set,
+            resolve_buffer,
+            readback_buffer: Arc::new(readback_buffer),
+            period,
+            pending: Arc::new(AtomicBool::new(false)),
+            recording: false,
+        }
+    }
+    pub fn begin(&mut self, encoder: &mut CommandEncoder) {
+        self.recording = !self.pending.load(Ordering::Acquire);
+        if self.recording {
+            encoder.write_timestamp(&self.query_set, 0);
+        }
+    }
+    pub fn end(&mut self, encoder: &mut CommandEncoder) {
+        if !self.recording {
+            return;
+        }
+        encoder.write_timestamp(&self.query_set, 1);
+        encoder.resolve_query_set(&self.query_set, 0..2, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            Self::SIZE,
+        );
+    }
+    /// Start reading back the timestamps, which must only be called once the encoder passed to
+    /// `end` has been submitted
+    pub fn read(&mut self) {
+        if !std::mem::take(&mut self.recording) {
+            return;
+        }
+        self.pending.store(true, Ordering::Release);
+        let (label, period) = (self.label, self.period);
+        let buffer = self.readback_buffer.clone();
+        let pending = self.pending.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| {
+                if result.is_ok() {
+                    let timestamps: [u64; 2] =
+                        bytemuck::pod_read_unaligned(&buffer.slice(..).get_mapped_range());
+                    let ticks = timestamps[1].wrapping_sub(timestamps[0]);
+                    let duration = Duration::from_nanos((ticks as f64 * period as f64) as u64);
+                    tracing::info!("{label} took {duration:?} on the GPU");
+                    buffer.unmap();
+                }
+                pending.store(false, Ordering::Release);
+            });
+    }
+}
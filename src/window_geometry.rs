@@ -0,0 +1,73 @@
+use std::{io, path::PathBuf};
+
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    monitor::MonitorHandle,
+    window::{Window, WindowBuilder},
+};
+
+use crate::config;
+
+/// The size and position of the window, which is saved when it closes so the next run can open in
+/// the same place
+pub struct WindowGeometry {
+    position: PhysicalPosition<i32>,
+    size: PhysicalSize<u32>,
+    maximized: bool,
+}
+impl WindowGeometry {
+    pub fn from_window(window: &Window) -> Option<Self> {
+        Some(Self {
+            position: window.outer_position().ok()?,
+            size: window.inner_size(),
+            maximized: window.is_maximized(),
+        })
+    }
+    pub fn apply(&self, builder: WindowBuilder) -> WindowBuilder {
+        builder
+            .with_position(self.position)
+            .with_inner_size(self.size)
+            .with_maximized(self.maximized)
+    }
+    /// Whether some of the window would be on one of the monitors, since a monitor that the window
+    /// was on last time might not be plugged in anymore
+    pub fn is_visible(&self, mut monitors: impl Iterator<Item = MonitorHandle>) -> bool {
+        let (x, y) = (self.position.x as i64, self.position.y as i64);
+        let (width, height) = (self.size.width as i64, self.size.height as i64);
+        monitors.any(|monitor| {
+            let (mx, my) = (monitor.position().x as i64, monitor.position().y as i64);
+            let size = monitor.size();
+            x < mx + size.width as i64
+                && mx < x + width
+                && y < my + size.height as i64
+                && my < y + height
+        })
+    }
+    /// Load the geometry saved by the last run, if there is one
+    pub fn load() -> Option<Self> {
+        let contents = std::fs::read_to_string(Self::path()?).ok()?;
+        let mut values = contents.split_whitespace().map(str::parse::<i64>);
+        let mut next = || values.next()?.ok();
+        Some(Self {
+            position: PhysicalPosition::new(next()? as i32, next()? as i32),
+            size: PhysicalSize::new(next()? as u32, next()? as u32),
+            maximized: next()? != 0,
+        })
+    }
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path().ok_or(io::ErrorKind::NotFound)?;
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        let contents = format!(
+            "{} {} {} {} {}\n",
+            self.position.x,
+            self.position.y,
+            self.size.width,
+            self.size.height,
+            self.maximized as u8
+        );
+        std::fs::write(path, contents)
+    }
+    fn path() -> Option<PathBuf> {
+        Some(config::dir()?.join("window"))
+    }
+}